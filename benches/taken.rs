@@ -0,0 +1,65 @@
+//! Finding a free name in a destination directory with many existing collisions, by asking the
+//! filesystem about each candidate and by asking `Taken`.
+//!
+//! Run with `cargo bench --bench taken`. The directory is made under the system temporary
+//! directory, or under `CU_BACKFILL_BENCH_DIR` if that's set (e.g. to a network mount, where the
+//! difference is largest).
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[allow(dead_code)]
+#[path = "../src/taken.rs"]
+mod taken;
+
+use taken::Taken;
+
+/// Seconds with a burst of photos in them.
+const BURSTS: u32 = 300;
+
+/// Photos already in the destination from each burst.
+const PER_BURST: u32 = 100;
+
+fn name(burst: u32, n: u32) -> String {
+    let base = format!("2019-04-02 10.{:02}.{:02}", burst / 60, burst % 60);
+    if n == 0 { format!("{base}.jpg") } else { format!("{base}-{n}.jpg") }
+}
+
+/// Find a free name for one more photo from each burst, using `taken` to tell whether a name is
+/// taken, and return how many candidates were looked at.
+fn fill(dir: &Path, mut taken: impl FnMut(&Path) -> bool) -> u64 {
+    let mut tried = 0;
+    for burst in 0..BURSTS {
+        let mut n = 0;
+        loop {
+            tried += 1;
+            if !taken(&dir.join(name(burst, n))) {
+                break;
+            }
+            n += 1;
+        }
+    }
+    tried
+}
+
+fn main() -> std::io::Result<()> {
+    let root = std::env::var_os("CU_BACKFILL_BENCH_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+    let dir = root.join(format!("cu_backfill-bench-{}", std::process::id()));
+    std::fs::create_dir(&dir)?;
+    for burst in 0..BURSTS {
+        for n in 0..PER_BURST {
+            std::fs::File::create(dir.join(name(burst, n)))?;
+        }
+    }
+
+    let start = Instant::now();
+    let tried = fill(&dir, Path::exists);
+    println!("stat per candidate: {:?} for {tried} candidates", start.elapsed());
+
+    let start = Instant::now();
+    let mut taken = Taken::default();
+    let tried = fill(&dir, |path| taken.contains(path));
+    println!("Taken:              {:?} for {tried} candidates, including listing the directory", start.elapsed());
+
+    std::fs::remove_dir_all(&dir)
+}
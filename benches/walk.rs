@@ -0,0 +1,65 @@
+//! Walking a source tree one directory at a time, as walkdir did, and with jwalk, which reads
+//! directories on a thread pool.
+//!
+//! Run with `cargo bench --bench walk`. The tree is made under the system temporary directory,
+//! or under `CU_BACKFILL_BENCH_DIR` if that's set (e.g. to a network mount, where reading
+//! directories is slow and the difference is largest).
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Directories in the tree, like one per day of a few years of photos.
+const DIRS: u32 = 1000;
+
+/// Files in each directory.
+const PER_DIR: u32 = 20;
+
+/// Walk the tree in sorted order, reading one directory at a time, and return how many files
+/// are in it.
+fn walk_serial(dir: &Path) -> std::io::Result<u64> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    let mut files = 0;
+    for entry in entries {
+        if entry.file_type()?.is_dir() {
+            files += walk_serial(&entry.path())?;
+        } else {
+            files += 1;
+        }
+    }
+    Ok(files)
+}
+
+/// Walk the tree in sorted order the way the main loop does, and return how many files are in
+/// it.
+fn walk_parallel(dir: &Path) -> u64 {
+    jwalk::WalkDir::new(dir)
+        .sort(true)
+        .skip_hidden(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_type().is_dir())
+        .count() as u64
+}
+
+fn main() -> std::io::Result<()> {
+    let root = std::env::var_os("CU_BACKFILL_BENCH_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+    let dir = root.join(format!("cu_backfill-bench-walk-{}", std::process::id()));
+    for d in 0..DIRS {
+        let sub = dir.join(format!("{}", 2015 + d / 365)).join(format!("{:03}", d % 365));
+        std::fs::create_dir_all(&sub)?;
+        for n in 0..PER_DIR {
+            std::fs::File::create(sub.join(format!("IMG_{n:04}.jpg")))?;
+        }
+    }
+
+    let start = Instant::now();
+    let files = walk_serial(&dir)?;
+    println!("one directory at a time: {:?} for {files} files", start.elapsed());
+
+    let start = Instant::now();
+    let files = walk_parallel(&dir);
+    println!("jwalk:                   {:?} for {files} files", start.elapsed());
+
+    std::fs::remove_dir_all(&dir)
+}
@@ -0,0 +1,297 @@
+//! Reading metadata out of ISO base media files (MP4, MOV, HEIF, AVIF, and friends).
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use chrono::{Datelike, TimeZone, Utc};
+
+/// Seconds from 1904-01-01 (the epoch of ISO base media timestamps) to 1970-01-01.
+const EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Where a box's contents are in the file: (start, length).
+type Span = (u64, u64);
+
+/// Latest year a movie header may give; anything after this is a corrupt header.
+const MAX_YEAR: i32 = 9999;
+
+/// Largest item that will be read into memory; EXIF data is much smaller than this.
+const MAX_ITEM_LEN: u64 = 16 << 20;
+
+/// Find the first box of the given type among the boxes that make up `within`.
+pub fn find_box<R: Read + Seek>(r: &mut R, within: Span, kind: &[u8; 4]) -> io::Result<Option<Span>> {
+    let (start, len) = within;
+    let end = start + len;
+    let mut pos = start;
+    while pos + 8 <= end {
+        r.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let (header_len, box_len) = match size {
+            // The box extends to the end of its container.
+            0 => (8, end - pos),
+            1 => {
+                let mut large = [0u8; 8];
+                r.read_exact(&mut large)?;
+                (16, u64::from_be_bytes(large))
+            }
+            n => (8, u64::from(n)),
+        };
+        if box_len < header_len || pos.checked_add(box_len).is_none_or(|box_end| box_end > end) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad box size at offset {pos}")));
+        }
+        if &header[4..8] == kind {
+            return Ok(Some((pos + header_len, box_len - header_len)));
+        }
+        pos += box_len;
+    }
+    Ok(None)
+}
+
+/// Follow a path of nested boxes from the top level of the file.
+pub fn find_path<R: Read + Seek>(r: &mut R, path: &[&[u8; 4]]) -> io::Result<Option<Span>> {
+    let mut span = (0, r.seek(SeekFrom::End(0))?);
+    for kind in path {
+        match find_box(r, span, kind)? {
+            Some(inner) => span = inner,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(span))
+}
+
+/// The creation time of a movie, from its movie header (`moov/mvhd`), if it's set.
+pub fn creation_time<R: Read + Seek>(mut r: R) -> io::Result<Option<chrono::DateTime<Utc>>> {
+    let Some((start, len)) = find_path(&mut r, &[b"moov", b"mvhd"])? else {
+        return Ok(None);
+    };
+    r.seek(SeekFrom::Start(start))?;
+    let mut version = [0u8; 4];
+    r.read_exact(&mut version)?;
+    let secs = match version[0] {
+        0 if len >= 8 => {
+            let mut b = [0u8; 4];
+            r.read_exact(&mut b)?;
+            u64::from(u32::from_be_bytes(b))
+        }
+        1 if len >= 12 => {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            u64::from_be_bytes(b)
+        }
+        v => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported mvhd version {v}")));
+        }
+    };
+    // Zero is what most tools write when they don't know.
+    if secs == 0 {
+        return Ok(None);
+    }
+    let unix = i64::try_from(secs).ok().and_then(|s| s.checked_sub(EPOCH_OFFSET));
+    match unix.and_then(|s| Utc.timestamp_opt(s, 0).single()) {
+        Some(time) if time.year() > MAX_YEAR => {
+            Err(io::Error::new(io::ErrorKind::InvalidData, format!("implausible creation time {time} in mvhd")))
+        }
+        time => Ok(time),
+    }
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_be_bytes(b))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
+}
+
+/// Read an unsigned integer of 0, 4, or 8 bytes, as used in the `iloc` box.
+fn read_sized<R: Read>(r: &mut R, size: u8) -> io::Result<u64> {
+    match size {
+        0 => Ok(0),
+        4 => read_u32(r).map(u64::from),
+        8 => {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Ok(u64::from_be_bytes(b))
+        }
+        n => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad field size {n} in iloc"))),
+    }
+}
+
+/// Skip the version and flags of a full box, returning the version.
+fn full_box_version<R: Read + Seek>(r: &mut R, span: Span) -> io::Result<u8> {
+    r.seek(SeekFrom::Start(span.0))?;
+    Ok(read_u32(r)?.to_be_bytes()[0])
+}
+
+/// The TIFF data in an Exif item or box, which starts with the offset of the TIFF header.
+pub fn exif_tiff(data: &[u8]) -> Option<&[u8]> {
+    let offset = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+    data.get(4 + usize::try_from(offset).ok()? ..)
+}
+
+/// The ID of the first item of type `Exif`, from the `iinf` box.
+fn exif_item_id<R: Read + Seek>(r: &mut R, iinf: Span) -> io::Result<Option<u32>> {
+    let version = full_box_version(r, iinf)?;
+    let count = if version == 0 { u64::from(read_u16(r)?) } else { u64::from(read_u32(r)?) };
+    let header_len = 4 + if version == 0 { 2 } else { 4 };
+    let mut entries = (iinf.0 + header_len, iinf.1.saturating_sub(header_len));
+    for _ in 0..count {
+        let Some(infe) = find_box(r, entries, b"infe")? else {
+            break;
+        };
+        // Only versions 2 and up have an item type.
+        let version = full_box_version(r, infe)?;
+        let id = match version {
+            2 => u32::from(read_u16(r)?),
+            3 => read_u32(r)?,
+            _ => 0,
+        };
+        if version >= 2 {
+            let _protection_index = read_u16(r)?;
+            let mut kind = [0u8; 4];
+            r.read_exact(&mut kind)?;
+            if &kind == b"Exif" {
+                return Ok(Some(id));
+            }
+        }
+        let next = infe.0 + infe.1;
+        entries = (next, (entries.0 + entries.1).saturating_sub(next));
+    }
+    Ok(None)
+}
+
+/// Read the data of an item, as located by the `iloc` box.
+fn item_data<R: Read + Seek>(r: &mut R, iloc: Span, item_id: u32) -> io::Result<Option<Vec<u8>>> {
+    let version = full_box_version(r, iloc)?;
+    let sizes = read_u16(r)?;
+    let offset_size = (sizes >> 12) as u8;
+    let length_size = (sizes >> 8 & 0xF) as u8;
+    let base_offset_size = (sizes >> 4 & 0xF) as u8;
+    let index_size = if version >= 1 { (sizes & 0xF) as u8 } else { 0 };
+    let count = if version < 2 { read_u16(r)?.into() } else { read_u32(r)? };
+    for _ in 0..count {
+        let id = if version < 2 { read_u16(r)?.into() } else { read_u32(r)? };
+        let construction_method = if version >= 1 { read_u16(r)? & 0xF } else { 0 };
+        let _data_reference_index = read_u16(r)?;
+        let base_offset = read_sized(r, base_offset_size)?;
+        let extent_count = read_u16(r)?;
+        let mut extents = vec![];
+        for _ in 0..extent_count {
+            let _index = read_sized(r, index_size)?;
+            let offset = read_sized(r, offset_size)?;
+            let length = read_sized(r, length_size)?;
+            let offset = base_offset.checked_add(offset)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad extent offset in iloc"))?;
+            extents.push((offset, length));
+        }
+        if id != item_id {
+            continue;
+        }
+        if construction_method != 0 {
+            // Data stored in the idat box or in other items; not seen in practice for Exif.
+            return Ok(None);
+        }
+        let total = extents.iter().try_fold(0u64, |total, &(_, length)| total.checked_add(length));
+        if total.is_none_or(|total| total > MAX_ITEM_LEN) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "item is implausibly large"));
+        }
+        let mut data = vec![];
+        for (offset, length) in extents {
+            r.seek(SeekFrom::Start(offset))?;
+            let start = data.len();
+            data.resize(start + length as usize, 0);
+            r.read_exact(&mut data[start..])?;
+        }
+        return Ok(Some(data));
+    }
+    Ok(None)
+}
+
+/// The TIFF data of the Exif item of a HEIF or AVIF image, if it has one.
+pub fn exif_item<R: Read + Seek>(mut r: R) -> io::Result<Option<Vec<u8>>> {
+    let Some(meta) = find_path(&mut r, &[b"meta"])? else {
+        return Ok(None);
+    };
+    // The meta box is a full box: its children start after the version and flags.
+    let children = (meta.0 + 4, meta.1.saturating_sub(4));
+    let Some(iinf) = find_box(&mut r, children, b"iinf")? else {
+        return Ok(None);
+    };
+    let Some(id) = exif_item_id(&mut r, iinf)? else {
+        return Ok(None);
+    };
+    let Some(iloc) = find_box(&mut r, children, b"iloc")? else {
+        return Ok(None);
+    };
+    let data = item_data(&mut r, iloc, id)?;
+    Ok(data.and_then(|data| exif_tiff(&data).map(<[u8]>::to_vec)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use exif::{In, Reader, Tag};
+
+    #[test]
+    fn exif_item_of_avif() {
+        let file = File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/exif.avif")).unwrap();
+        let tiff = exif_item(file).unwrap().expect("no Exif item found");
+        let exif = Reader::new().read_raw(tiff).unwrap();
+        let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).unwrap();
+        assert_eq!(field.display_value().to_string(), "2020-07-15 08:09:10");
+    }
+
+    /// A movie with a version 1 movie header giving this creation time.
+    fn movie(secs: u64) -> Vec<u8> {
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend_from_slice(&secs.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 20]);
+        let mut moov = (8 + 8 + mvhd.len() as u32).to_be_bytes().to_vec();
+        moov.extend_from_slice(b"moov");
+        moov.extend_from_slice(&(8 + mvhd.len() as u32).to_be_bytes());
+        moov.extend_from_slice(b"mvhd");
+        moov.extend_from_slice(&mvhd);
+        moov
+    }
+
+    #[test]
+    fn creation_time_of_mp4() {
+        let file = File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mvhd.mp4")).unwrap();
+        let time = creation_time(file).unwrap().expect("no creation time found");
+        assert_eq!(time, Utc.with_ymd_and_hms(2018, 8, 9, 7, 6, 5).unwrap());
+    }
+
+    #[test]
+    fn far_future_creation_time_is_an_error() {
+        // In the year 100000 or so, which chrono can represent but a file name shouldn't.
+        let secs = 3_155_760_000_000 + EPOCH_OFFSET as u64;
+        let e = creation_time(Cursor::new(movie(secs))).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(creation_time(Cursor::new(movie(0))).unwrap(), None);
+    }
+
+    #[test]
+    fn overflowing_extent_is_an_error() {
+        let mut iloc = vec![0, 0, 0, 0];
+        // 8-byte offsets and base offset, 4-byte lengths; one item with one extent.
+        iloc.extend_from_slice(&0x8480u16.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&0u16.to_be_bytes());
+        iloc.extend_from_slice(&u64::MAX.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&1u64.to_be_bytes());
+        iloc.extend_from_slice(&4u32.to_be_bytes());
+        let len = iloc.len() as u64;
+        let e = item_data(&mut Cursor::new(iloc), (0, len), 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,140 @@
+//! Naming conventions of action cameras and drones: GoPro chapters, and the proxy, thumbnail, and
+//! telemetry files that go along with a video.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const VIDEO_EXTENSIONS: &[&str] = &["MP4", "MOV"];
+
+/// GoPro low-res proxies and thumbnails, and DJI telemetry subtitles.
+const SIDECAR_EXTENSIONS: &[&str] = &["LRV", "THM", "SRT"];
+
+/// Split a GoPro file name like `GX010123` into its kind (`H` or `X` for the video codec, `L` for
+/// a low-res proxy), chapter number, and video number.
+fn gopro_parts(stem: &str) -> Option<(u8, u32, &str)> {
+    let b = stem.as_bytes();
+    if b.len() != 8 || b[0] != b'G' || !matches!(b[1], b'H' | b'X' | b'L') || !b[2..].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some((b[1], stem[2..4].parse().ok()?, &stem[4..]))
+}
+
+/// A sibling of `path` with the given stem and one of the given extensions, in upper or lower
+/// case, if it exists.
+fn sibling(path: &Path, stem: &str, extensions: &[&str]) -> Option<PathBuf> {
+    extensions.iter()
+        .flat_map(|ext| [ext.to_string(), ext.to_ascii_lowercase()])
+        .map(|ext| path.with_file_name(format!("{stem}.{ext}")))
+        .find(|p| p.is_file())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+pub struct Chapter {
+    pub number: u32,
+    /// The first chapter of the same video (which may be the file itself).
+    pub first: PathBuf,
+}
+
+/// The first chapter of the GoPro video that `path` (chapter `number` of it) is part of, if the
+/// video has more than one chapter.
+fn first_chapter(path: &Path, kind: char, number: u32, video: &str) -> Option<PathBuf> {
+    if number == 1 {
+        // Only a video that has a second chapter is chaptered at all.
+        sibling(path, &format!("G{kind}02{video}"), VIDEO_EXTENSIONS)?;
+        Some(path.to_owned())
+    } else {
+        sibling(path, &format!("G{kind}01{video}"), VIDEO_EXTENSIONS)
+    }
+}
+
+/// The GoPro videos seen so far, each looked up once: with --move, the first chapter is gone from
+/// the source by the time the ones after it come up.
+#[derive(Default)]
+pub struct Chapters {
+    /// The first chapter of each video, by the video's name without the chapter number, or
+    /// `None` if it isn't chaptered.
+    firsts: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+impl Chapters {
+    /// If this is one chapter of a GoPro video that was split into several files, which one it is.
+    pub fn get(&mut self, path: &Path) -> Option<Chapter> {
+        if !has_extension(path, VIDEO_EXTENSIONS) {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let (kind, number, video) = gopro_parts(stem)?;
+        if kind == b'L' {
+            return None;
+        }
+        let kind = char::from(kind);
+        let key = path.with_file_name(format!("G{kind}{video}"));
+        let first = self.firsts.entry(key)
+            .or_insert_with(|| first_chapter(path, kind, number, video))
+            .clone()?;
+        Some(Chapter { number, first })
+    }
+}
+
+/// Whether this is a sidecar file of a video next to it, which is copied (or skipped) along with
+/// the video instead of on its own.
+pub fn is_sidecar(path: &Path) -> bool {
+    if !has_extension(path, SIDECAR_EXTENSIONS) {
+        return false;
+    }
+    let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    let mut stems = vec![stem.to_owned()];
+    if let Some((b'L', _, _)) = gopro_parts(stem) {
+        stems.push(format!("GH{}", &stem[2..]));
+        stems.push(format!("GX{}", &stem[2..]));
+    }
+    stems.iter().any(|stem| sibling(path, stem, VIDEO_EXTENSIONS).is_some())
+}
+
+/// The sidecar files of a video.
+pub fn sidecars(path: &Path) -> Vec<PathBuf> {
+    if !has_extension(path, VIDEO_EXTENSIONS) {
+        return vec![];
+    }
+    let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+        return vec![];
+    };
+    let mut stems = vec![stem.to_owned()];
+    if let Some((b'H' | b'X', _, _)) = gopro_parts(stem) {
+        stems.push(format!("GL{}", &stem[2..]));
+    }
+    stems.iter()
+        .flat_map(|stem| SIDECAR_EXTENSIONS.iter().filter_map(move |&ext| sibling(path, stem, &[ext])))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_are_remembered() {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-clips-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let (first, second) = (dir.join("GX010123.MP4"), dir.join("GX020123.MP4"));
+        std::fs::write(&first, b"").unwrap();
+        std::fs::write(&second, b"").unwrap();
+        std::fs::write(dir.join("GX010124.MP4"), b"").unwrap();
+
+        let mut chapters = Chapters::default();
+        assert_eq!(chapters.get(&first).map(|c| (c.number, c.first)), Some((1, first.clone())));
+        // Moved away, as with --move.
+        std::fs::remove_file(&first).unwrap();
+        assert_eq!(chapters.get(&second).map(|c| (c.number, c.first)), Some((2, first.clone())));
+        assert!(chapters.get(&dir.join("GX010124.MP4")).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Classifying what a run would do with each file, for --diff and --verify-only.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Class {
+    /// Would be copied to a name nobody has.
+    New,
+    /// An identical file is already in the destination.
+    Duplicate,
+    /// Its name is taken by a different file, so it would get a suffix.
+    Conflict,
+}
+
+type Classes = [(Class, &'static str, &'static str); 3];
+
+const CLASSES: Classes = [
+    (Class::New, "new", "new files"),
+    (Class::Duplicate, "duplicate", "already in the destination"),
+    (Class::Conflict, "conflict", "conflicting with a different file"),
+];
+
+/// The same classes, worded for checking a destination that should have everything already.
+const AUDIT_CLASSES: Classes = [
+    (Class::New, "missing", "missing from the destination"),
+    (Class::Duplicate, "present", "present in the destination"),
+    (Class::Conflict, "mismatched", "different from the file with their name"),
+];
+
+/// Source files, and their destinations, by class.
+#[derive(Debug, Default)]
+pub struct Diff {
+    files: [Vec<(PathBuf, PathBuf)>; CLASSES.len()],
+    audit: bool,
+    /// Destination files that no source file accounts for, if they were looked for.
+    extra: Option<Vec<PathBuf>>,
+}
+
+impl Diff {
+    /// A diff for --verify-only.
+    pub fn audit() -> Self {
+        Self { audit: true, ..Default::default() }
+    }
+
+    fn classes(&self) -> &'static Classes {
+        if self.audit { &AUDIT_CLASSES } else { &CLASSES }
+    }
+
+    pub fn set_extra(&mut self, extra: Vec<PathBuf>) {
+        self.extra = Some(extra);
+    }
+
+    /// Whether every source file is in the destination, and nothing else is.
+    pub fn is_clean(&self) -> bool {
+        self.files[Class::New as usize].is_empty()
+            && self.files[Class::Conflict as usize].is_empty()
+            && self.extra.as_ref().is_none_or(Vec::is_empty)
+    }
+
+    pub fn add(&mut self, class: Class, src: &Path, dst: &Path) {
+        self.files[class as usize].push((src.to_owned(), dst.to_owned()));
+    }
+
+    /// Print the number of files in each class, and with `list`, the files themselves.
+    pub fn print(&self, list: bool) {
+        for &(class, _, description) in self.classes() {
+            let files = &self.files[class as usize];
+            eprintln!("{:>8} {description}", files.len());
+            if list {
+                for (src, dst) in files {
+                    eprintln!("    {src:?} -> {dst:?}");
+                }
+            }
+        }
+        if let Some(extra) = &self.extra {
+            eprintln!("{:>8} unexpected files in the destination", extra.len());
+            if list {
+                for path in extra {
+                    eprintln!("    {path:?}");
+                }
+            }
+        }
+    }
+
+    /// Write the files in each class to a file named after the class, in `dir`.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+        for &(class, name, _) in self.classes() {
+            let path = dir.join(format!("{name}.txt"));
+            let mut out = File::create(&path)
+                .map(BufWriter::new)
+                .with_context(|| format!("failed to create {path:?}"))?;
+            for (src, dst) in &self.files[class as usize] {
+                writeln!(out, "{src:?} -> {dst:?}").with_context(|| format!("failed to write {path:?}"))?;
+            }
+            out.flush().with_context(|| format!("failed to write {path:?}"))?;
+        }
+        if let Some(extra) = &self.extra {
+            let path = dir.join("extra.txt");
+            let mut out = File::create(&path)
+                .map(BufWriter::new)
+                .with_context(|| format!("failed to create {path:?}"))?;
+            for dst in extra {
+                writeln!(out, "{dst:?}").with_context(|| format!("failed to write {path:?}"))?;
+            }
+            out.flush().with_context(|| format!("failed to write {path:?}"))?;
+        }
+        Ok(())
+    }
+}
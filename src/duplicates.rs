@@ -0,0 +1,76 @@
+//! Grouping content-identical files, for --report-duplicates.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+#[derive(Debug, Default)]
+struct Group {
+    sources: Vec<PathBuf>,
+    destination: Option<PathBuf>,
+}
+
+impl Group {
+    fn len(&self) -> usize {
+        self.sources.len() + usize::from(self.destination.is_some())
+    }
+}
+
+/// Source files (and a matching destination file, if any) grouped by content hash.
+#[derive(Debug, Default)]
+pub struct DuplicateReport {
+    groups: HashMap<String, Group>,
+}
+
+impl DuplicateReport {
+    pub fn add_source(&mut self, hash: &str, path: &Path) {
+        self.groups.entry(hash.to_owned()).or_default().sources.push(path.to_owned());
+    }
+
+    pub fn set_destination(&mut self, hash: &str, path: &Path) {
+        self.groups.entry(hash.to_owned()).or_default().destination = Some(path.to_owned());
+    }
+
+    /// Groups with more than one member, in a stable order.
+    fn duplicate_groups(&self) -> Vec<&Group> {
+        let mut groups = self.groups.values().filter(|g| g.len() > 1).collect::<Vec<_>>();
+        groups.sort_by(|a, b| a.sources.cmp(&b.sources));
+        groups
+    }
+
+    /// Write the report to a file: as JSON if its name ends in ".json", or otherwise as plain
+    /// text with one path per line and a blank line between groups.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut out = BufWriter::new(File::create(path)
+            .with_context(|| format!("failed to create {path:?}"))?);
+        let groups = self.duplicate_groups();
+
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            let json = groups.iter()
+                .map(|g| serde_json::json!({
+                    "sources": g.sources.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+                    "destination": g.destination.as_ref().map(|p| p.to_string_lossy()),
+                }))
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut out, &json)?;
+            writeln!(out)?;
+        } else {
+            for (i, group) in groups.iter().enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                for source in &group.sources {
+                    writeln!(out, "{}", source.display())?;
+                }
+                if let Some(dst) = &group.destination {
+                    writeln!(out, "{} (destination)", dst.display())?;
+                }
+            }
+        }
+
+        out.flush().with_context(|| format!("failed to write {path:?}"))
+    }
+}
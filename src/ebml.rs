@@ -0,0 +1,121 @@
+//! Reading metadata out of Matroska and WebM files.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use chrono::{TimeZone, Utc};
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const CLUSTER: u32 = 0x1F43_B675;
+const DATE_UTC: u32 = 0x4461;
+
+/// Seconds from 1970-01-01 to 2001-01-01, the epoch of Matroska dates.
+const EPOCH_OFFSET: i64 = 978_307_200;
+
+/// The length of a variable-length integer, from its first byte.
+fn vint_len(first: u8) -> io::Result<usize> {
+    match first.leading_zeros() {
+        n @ 0..=7 => Ok(n as usize + 1),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid EBML variable-length integer")),
+    }
+}
+
+/// Read an element ID, which keeps its length marker bits.
+fn read_id<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    let len = vint_len(b[0])?;
+    if len > 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "EBML element ID is too long"));
+    }
+    let mut id = u32::from(b[0]);
+    for _ in 1..len {
+        r.read_exact(&mut b)?;
+        id = id << 8 | u32::from(b[0]);
+    }
+    Ok(id)
+}
+
+/// Read an element size; `None` means unknown (it extends to the end of its parent).
+fn read_size<R: Read>(r: &mut R) -> io::Result<Option<u64>> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    let len = vint_len(b[0])?;
+    let mut size = u64::from(b[0]) & (0xFF >> len);
+    let mut all_ones = size == 0xFF >> len;
+    for _ in 1..len {
+        r.read_exact(&mut b)?;
+        size = size << 8 | u64::from(b[0]);
+        all_ones &= b[0] == 0xFF;
+    }
+    Ok((!all_ones).then_some(size))
+}
+
+/// Find the first child element with the given ID between `pos` and `end`, returning where its
+/// contents start and end. The search stops at the first cluster, since all the metadata comes
+/// before it and clusters may not have a known size.
+fn find_element<R: Read + Seek>(r: &mut R, mut pos: u64, end: u64, want: u32) -> io::Result<Option<(u64, u64)>> {
+    while pos < end {
+        r.seek(SeekFrom::Start(pos))?;
+        let id = read_id(r)?;
+        let size = read_size(r)?;
+        let start = r.stream_position()?;
+        let element_end = size.map_or(end, |size| start.saturating_add(size).min(end));
+        if id == want {
+            return Ok(Some((start, element_end)));
+        }
+        if id == CLUSTER {
+            break;
+        }
+        pos = element_end;
+    }
+    Ok(None)
+}
+
+/// The date a Matroska or WebM file was made, from Segment → Info → DateUTC, if it's there.
+pub fn date_utc<R: Read + Seek>(mut r: R) -> io::Result<Option<chrono::DateTime<Utc>>> {
+    let end = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    if read_id(&mut r)? != EBML_HEADER {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Matroska file"));
+    }
+    let Some((segment, segment_end)) = find_element(&mut r, 0, end, SEGMENT)? else {
+        return Ok(None);
+    };
+    let Some((info, info_end)) = find_element(&mut r, segment, segment_end, INFO)? else {
+        return Ok(None);
+    };
+    let Some((date, date_end)) = find_element(&mut r, info, info_end, DATE_UTC)? else {
+        return Ok(None);
+    };
+    if date_end - date != 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "DateUTC element has the wrong size"));
+    }
+    r.seek(SeekFrom::Start(date))?;
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    let nanos = i64::from_be_bytes(b);
+    let secs = nanos.div_euclid(1_000_000_000) + EPOCH_OFFSET;
+    Ok(Utc.timestamp_opt(secs, nanos.rem_euclid(1_000_000_000) as u32).single())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::Path;
+
+    #[test]
+    fn date_utc_of_mkv() {
+        let file = File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/date.mkv")).unwrap();
+        let date = date_utc(file).unwrap().expect("no DateUTC found");
+        assert_eq!(date, Utc.with_ymd_and_hms(2021, 6, 5, 14, 30, 0).unwrap() + chrono::Duration::milliseconds(250));
+    }
+
+    #[test]
+    fn not_matroska() {
+        let e = date_utc(io::Cursor::new(b"RIFF\0\0\0\0AVI ")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,77 @@
+//! Asking exiftool for the dates of files we can't read ourselves, for --exiftool.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, anyhow, bail};
+use exif::DateTime;
+
+/// Tags to ask for, in order of preference.
+const TAGS: [&str; 3] = ["DateTimeOriginal", "CreateDate", "MediaCreateDate"];
+
+pub struct Exiftool {
+    program: PathBuf,
+    timeout: Duration,
+}
+
+impl Exiftool {
+    /// Check that exiftool can be run at all.
+    pub fn new(program: PathBuf, timeout: Duration) -> anyhow::Result<Self> {
+        let output = Command::new(&program)
+            .arg("-ver")
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("failed to run {program:?}; is exiftool installed?"))?;
+        if !output.status.success() {
+            bail!("{program:?} -ver failed ({})", output.status);
+        }
+        Ok(Self { program, timeout })
+    }
+
+    /// The first of the date tags that exiftool finds in the file.
+    ///
+    /// Files are processed one at a time, so there is never more than one exiftool running.
+    pub fn datetime(&self, path: &Path) -> anyhow::Result<Option<DateTime>> {
+        let mut child = Command::new(&self.program)
+            .arg("-j")
+            .args(TAGS.map(|tag| format!("-{tag}")))
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("failed to run {:?}", self.program))?;
+
+        // The output is small enough to fit in the pipe, so it's safe to wait before reading it.
+        let started = Instant::now();
+        while child.try_wait()?.is_none() {
+            if started.elapsed() > self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("exiftool took longer than {:?}", self.timeout);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let output = child.wait_with_output()?;
+        // exiftool exits with an error for files it can't read at all, with nothing useful to say.
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .context("failed to parse exiftool's output")?;
+        let tags = json.get(0).ok_or_else(|| anyhow!("exiftool's output is empty"))?;
+        for tag in TAGS {
+            let Some(value) = tags[tag].as_str() else {
+                continue;
+            };
+            // Ignore any subseconds or time zone after the "YYYY:MM:DD HH:MM:SS".
+            let value = value.get(..19).unwrap_or(value);
+            if let Ok(dt) = DateTime::from_ascii(value.as_bytes()) {
+                return Ok(Some(dt));
+            }
+        }
+        Ok(None)
+    }
+}
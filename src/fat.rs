@@ -0,0 +1,90 @@
+//! Staying within the limits of FAT32 and exFAT destinations, for --fat-safe.
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fat {
+    Fat32,
+    ExFat,
+}
+
+/// Largest file FAT32 can hold.
+pub const MAX_FAT32_FILE_SIZE: u64 = (4 << 30) - 1;
+
+/// Find out whether the filesystem holding `path` is FAT32 or exFAT.
+#[cfg(target_os = "linux")]
+pub fn detect(path: &Path) -> Option<Fat> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+    const EXFAT_SUPER_MAGIC: i64 = 0x2011_bab0;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: the path is NUL-terminated, and statfs fills in the struct when it succeeds.
+    let stat = unsafe {
+        if libc::statfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)] // f_type's type differs between platforms.
+    match stat.f_type as i64 {
+        MSDOS_SUPER_MAGIC => Some(Fat::Fat32),
+        EXFAT_SUPER_MAGIC => Some(Fat::ExFat),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect(_path: &Path) -> Option<Fat> {
+    None
+}
+
+/// Make a file name valid on FAT: characters it can't store become `_`, and trailing dots and
+/// spaces (which it drops) are removed from the stem and from the end of the name.
+pub fn sanitize_name(name: &str) -> String {
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+    let clean = |s: &str| {
+        s.chars()
+            .map(|c| if c.is_control() || r#""*/:<>?\|"#.contains(c) { '_' } else { c })
+            .collect::<String>()
+    };
+    let stem = clean(stem);
+    let stem = stem.trim_end_matches(['.', ' ']);
+    let name = format!("{stem}{}", clean(ext));
+    name.trim_end_matches(['.', ' ']).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_replaces_characters_fat_cant_store() {
+        assert_eq!(sanitize_name("2019-04-02 10.11.12 (a:b?).jpg"), "2019-04-02 10.11.12 (a_b_).jpg");
+        assert_eq!(sanitize_name("tab\there.jpg"), "tab_here.jpg");
+        assert_eq!(sanitize_name("caf\u{e9} \u{1f4f7}.jpg"), "caf\u{e9} \u{1f4f7}.jpg");
+    }
+
+    #[test]
+    fn sanitize_trims_the_stem_only() {
+        assert_eq!(sanitize_name("name. .jpg"), "name.jpg");
+        assert_eq!(sanitize_name("name..."), "name");
+        assert_eq!(sanitize_name("name.jpg "), "name.jpg");
+        assert_eq!(sanitize_name(".hidden"), ".hidden");
+        assert_eq!(sanitize_name("no extension "), "no extension");
+    }
+
+    #[test]
+    fn detect_other_filesystems() {
+        assert_eq!(detect(Path::new("/nonexistent/cu_backfill")), None);
+        assert_eq!(detect(&std::env::temp_dir().join("\0")), None);
+        #[cfg(target_os = "linux")]
+        assert_eq!(detect(Path::new("/proc")), None);
+    }
+}
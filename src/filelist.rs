@@ -0,0 +1,24 @@
+//! Reading a list of files to process, for --files-from.
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::rawpath;
+
+/// Read a list of paths, one per line (or separated by NUL bytes if `nul` is set), from a file or
+/// from stdin if the path is "-". Relative paths are taken relative to `base`.
+pub fn read(list: &Path, nul: bool, base: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut data = vec![];
+    if list == Path::new("-") {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        std::fs::File::open(list)?.read_to_end(&mut data)?;
+    }
+
+    let separator = if nul { b'\0' } else { b'\n' };
+    Ok(data.split(|&b| b == separator)
+        .map(|line| if nul { line } else { line.strip_suffix(b"\r").unwrap_or(line) })
+        .filter(|line| !line.is_empty())
+        .map(|line| base.join(rawpath::from_bytes(line)))
+        .collect())
+}
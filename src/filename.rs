@@ -0,0 +1,236 @@
+//! Recognizing dates that are embedded in file names.
+
+use std::path::Path;
+
+use exif::DateTime;
+use unicode_normalization::UnicodeNormalization;
+
+/// Try to get a date and time from a file's name (without its extension).
+///
+/// Patterns are tried in order of priority; the first one that matches wins.
+pub fn filename_datetime(stem: &str) -> Option<DateTime> {
+    camera_uploads_datetime(stem).map(|(dt, _)| dt)
+}
+
+/// Recognize a name in Dropbox Camera Uploads style: `2017-09-03 14.22.10`, optionally followed
+/// by a `-N` or `-<8 hex digits>` collision suffix.
+///
+/// Returns the date and time, and whether the name consists of nothing but the pattern (and
+/// suffix).
+pub fn camera_uploads_datetime(stem: &str) -> Option<(DateTime, bool)> {
+    let b = stem.as_bytes();
+    if b.len() < 19 {
+        return None;
+    }
+    for (i, sep) in [(4, b'-'), (7, b'-'), (10, b' '), (13, b'.'), (16, b'.')] {
+        if b[i] != sep {
+            return None;
+        }
+    }
+
+    let dt = DateTime {
+        year: digits(&b[0..4])?.try_into().ok()?,
+        month: digits(&b[5..7])?.try_into().ok()?,
+        day: digits(&b[8..10])?.try_into().ok()?,
+        hour: digits(&b[11..13])?.try_into().ok()?,
+        minute: digits(&b[14..16])?.try_into().ok()?,
+        second: digits(&b[17..19])?.try_into().ok()?,
+        nanosecond: None,
+        offset: None,
+    };
+    if !is_valid(&dt) {
+        return None;
+    }
+
+    let rest = &b[19..];
+    let exact = rest.is_empty()
+        || (rest[0] == b'-' && (digits(&rest[1..]).is_some() || is_hash_suffix(&rest[1..])));
+
+    Some((dt, exact))
+}
+
+fn is_hash_suffix(s: &[u8]) -> bool {
+    s.len() == 8 && s.iter().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn digits(s: &[u8]) -> Option<u32> {
+    if s.is_empty() || s.len() > 9 || !s.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(s.iter().fold(0, |acc, &d| acc * 10 + u32::from(d - b'0')))
+}
+
+fn is_valid(dt: &DateTime) -> bool {
+    chrono::NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())
+        .and_then(|d| d.and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into()))
+        .is_some()
+}
+
+/// Longest file name, in bytes, that common filesystems (ext4, APFS, NTFS) allow. FAT's limit of
+/// 255 UTF-16 units is never tighter than this.
+pub const MAX_NAME_BYTES: usize = 255;
+
+/// Longest generated name, in bytes: room is left for the files named after it, like the
+/// ".origin.json" sidecar of --record-origin.
+pub const MAX_GENERATED_NAME_BYTES: usize = MAX_NAME_BYTES - crate::origin::SIDECAR_SUFFIX.len();
+
+/// The longest prefix of `s` that fits in `max_bytes`, cut on a character boundary.
+pub fn truncate(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Longest original name, in characters, that will be embedded into a generated name.
+const MAX_ORIGINAL_NAME_CHARS: usize = 64;
+
+/// Clean up a file's original name (without extension) so it can be embedded into a generated
+/// name: path separators and control characters are removed and the result is length-capped.
+pub fn sanitize_original_name(stem: &str) -> String {
+    let cleaned = stem
+        .chars()
+        .filter(|&c| c != '/' && c != '\\' && !c.is_control())
+        .take(MAX_ORIGINAL_NAME_CHARS)
+        .collect::<String>();
+    cleaned.trim().to_owned()
+}
+
+/// Put a generated name together: the date-based `base`, the file's `original` name in
+/// parentheses (if there is one), the collision `suffix` (if any), and the extension.
+///
+/// If the name would be longer than [`MAX_GENERATED_NAME_BYTES`], only the original name gives
+/// way. The second value says whether it had to.
+pub fn generated_name(base: &str, original: &str, suffix: &str, ext: Option<&str>) -> (String, bool) {
+    let mut tail = String::new();
+    if !suffix.is_empty() {
+        tail.push('-');
+        tail += suffix;
+    }
+    if let Some(ext) = ext {
+        tail.push('.');
+        tail += ext;
+    }
+    // Names from a Mac may be decomposed; other systems expect them composed.
+    let tail = tail.nfc().collect::<String>();
+    let original = original.nfc().collect::<String>();
+    let mut name = base.nfc().collect::<String>();
+    let mut shortened = false;
+    if !original.is_empty() {
+        let room = MAX_GENERATED_NAME_BYTES.saturating_sub(name.len() + " ()".len() + tail.len());
+        let kept = truncate(&original, room).trim_end();
+        shortened = kept.len() < original.len();
+        if !kept.is_empty() {
+            name += &format!(" ({kept})");
+        }
+    }
+    name += &tail;
+    (name, shortened)
+}
+
+/// Longest path, in UTF-16 units and counting the NUL at the end, that Windows programs without
+/// long path support can open.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Why a destination path can't be used, if it can't: the name is too long even with no original
+/// name left in it (e.g. because of a very long extension), or on Windows, the whole path is.
+pub fn length_problem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.len();
+    if name > MAX_GENERATED_NAME_BYTES {
+        return Some(format!("its name would be {name} bytes long, but generated names are kept to \
+            {MAX_GENERATED_NAME_BYTES}"));
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        let len = path.as_os_str().encode_wide().count();
+        if len >= MAX_PATH {
+            return Some(format!("its path would be {len} characters long, but Windows allows {}", MAX_PATH - 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "2019-04-02 10.11.12";
+
+    #[test]
+    fn original_name_keeps_dots() {
+        assert_eq!(sanitize_original_name("IMG_1234.final.v2"), "IMG_1234.final.v2");
+    }
+
+    #[test]
+    fn original_name_removes_separators_and_control_characters() {
+        assert_eq!(sanitize_original_name("a/b\\c\td\u{7}"), "abcd");
+        assert_eq!(sanitize_original_name("  spaced out  "), "spaced out");
+    }
+
+    #[test]
+    fn original_name_keeps_unicode_and_caps_characters() {
+        assert_eq!(sanitize_original_name("Café 東京"), "Café 東京");
+        let long = "é".repeat(100);
+        assert_eq!(sanitize_original_name(&long).chars().count(), MAX_ORIGINAL_NAME_CHARS);
+    }
+
+    #[test]
+    fn generated_name_with_original() {
+        let (name, shortened) = generated_name(BASE, "IMG_1234.final", "", Some("jpg"));
+        assert_eq!(name, "2019-04-02 10.11.12 (IMG_1234.final).jpg");
+        assert!(!shortened);
+    }
+
+    #[test]
+    fn generated_name_puts_suffix_after_original() {
+        let (name, _) = generated_name(BASE, "IMG_1234", "1", Some("jpg"));
+        assert_eq!(name, "2019-04-02 10.11.12 (IMG_1234)-1.jpg");
+        let (name, _) = generated_name(BASE, "", "2", Some("JPG"));
+        assert_eq!(name, "2019-04-02 10.11.12-2.JPG");
+        let (name, _) = generated_name(BASE, "", "", None);
+        assert_eq!(name, BASE);
+    }
+
+    #[test]
+    fn truncate_multibyte() {
+        // Each of these is two bytes, so an odd limit lands in the middle of one.
+        let s = "\u{e9}\u{e9}\u{e9}";
+        assert_eq!(truncate(s, 6), s);
+        assert_eq!(truncate(s, 5), "\u{e9}\u{e9}");
+        assert_eq!(truncate(s, 1), "");
+        // Four bytes each.
+        assert_eq!(truncate("a\u{1f4f7}\u{1f4f7}", 8), "a\u{1f4f7}");
+    }
+
+    #[test]
+    fn generated_name_shortens_multibyte_original() {
+        let original = "\u{1f4f7}".repeat(60);
+        let (name, shortened) = generated_name(BASE, &original, "12", Some("jpg"));
+        assert!(shortened);
+        assert!(name.len() <= MAX_GENERATED_NAME_BYTES);
+        assert!(name.len() + ".origin.json".len() <= MAX_NAME_BYTES);
+        assert!(name.starts_with("2019-04-02 10.11.12 (\u{1f4f7}"));
+        assert!(name.ends_with("\u{1f4f7})-12.jpg"));
+    }
+
+    #[test]
+    fn too_long_without_original() {
+        let ext = "x".repeat(250);
+        let (name, shortened) = generated_name(BASE, "", "", Some(&ext));
+        assert!(!shortened);
+        assert!(length_problem(&Path::new("2019").join(name)).is_some());
+        assert_eq!(length_problem(&Path::new("2019").join(format!("{BASE}.jpg"))), None);
+    }
+
+    #[test]
+    fn generated_name_is_composed() {
+        let (name, _) = generated_name(BASE, "Cafe\u{301}", "", Some("jpg"));
+        assert_eq!(name, "2019-04-02 10.11.12 (Caf\u{e9}).jpg");
+    }
+}
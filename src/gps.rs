@@ -0,0 +1,68 @@
+//! Working out where and when (in absolute terms) a photo was taken, from its EXIF GPS tags.
+
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use exif::{Exif, In, Tag, Value};
+
+fn rationals(exif: &Exif, tag: Tag) -> Option<Vec<f64>> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(ref v) => Some(v.iter().map(|r| r.to_f64()).collect()),
+        _ => None,
+    }
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(ref v) if !v.is_empty() => Some(String::from_utf8_lossy(&v[0]).trim().to_owned()),
+        _ => None,
+    }
+}
+
+/// Degrees, minutes, and seconds to signed decimal degrees.
+fn degrees(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let dms = rationals(exif, tag)?;
+    let [d, m, s] = dms[..] else {
+        return None;
+    };
+    let value = d + m / 60. + s / 3600.;
+    if !value.is_finite() {
+        return None;
+    }
+    match ascii(exif, ref_tag) {
+        Some(r) if r.eq_ignore_ascii_case(negative_ref) => Some(-value),
+        _ => Some(value),
+    }
+}
+
+/// Latitude and longitude, in decimal degrees.
+pub fn coordinates(exif: &Exif) -> Option<(f64, f64)> {
+    let lat = degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let lon = degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+    (lat.abs() <= 90. && lon.abs() <= 180.).then_some((lat, lon))
+}
+
+/// The time a photo was taken as an absolute point in time, if the EXIF data has enough
+/// information for that: either GPS date and time stamps (which are in UTC), or
+/// DateTimeOriginal along with OffsetTimeOriginal.
+pub fn utc_datetime(exif: &Exif) -> Option<chrono::DateTime<Utc>> {
+    gps_timestamp(exif).or_else(|| offset_datetime(exif))
+}
+
+fn gps_timestamp(exif: &Exif) -> Option<chrono::DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(&ascii(exif, Tag::GPSDateStamp)?, "%Y:%m:%d").ok()?;
+    let hms = rationals(exif, Tag::GPSTimeStamp)?;
+    let [h, m, s] = hms[..] else {
+        return None;
+    };
+    if !(0. ..24.).contains(&h) || !(0. ..60.).contains(&m) || !(0. ..61.).contains(&s) {
+        return None;
+    }
+    let time = NaiveTime::from_hms_opt(h as u32, m as u32, s as u32)?;
+    Some(Utc.from_utc_datetime(&date.and_time(time)))
+}
+
+fn offset_datetime(exif: &Exif) -> Option<chrono::DateTime<Utc>> {
+    let dt = ascii(exif, Tag::DateTimeOriginal)?;
+    let offset = ascii(exif, Tag::OffsetTimeOriginal)?;
+    let with_offset = chrono::DateTime::parse_from_str(&format!("{dt} {offset}"), "%Y:%m:%d %H:%M:%S %:z").ok()?;
+    Some(with_offset.with_timezone(&Utc))
+}
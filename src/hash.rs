@@ -0,0 +1,65 @@
+//! Hashing file contents, for finding duplicates.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgorithm {
+    /// BLAKE3: fast, cryptographically strong.
+    Blake3,
+    /// SHA-256: slower, but matches what many other tools use.
+    Sha256,
+    /// XXH3 (128-bit): fastest, but not cryptographically strong.
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+}
+
+/// Call `f` with successive chunks of the file's contents.
+fn read_chunks(mut file: File, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        f(&buf[..n]);
+    }
+}
+
+/// Hash the contents of a file, returning the hash as a hex string.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    let file = File::open(path)?;
+    match algorithm {
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(file, |chunk| { hasher.update(chunk); })?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(file, |chunk| hasher.update(chunk))?;
+            Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+        }
+        HashAlgorithm::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            read_chunks(file, |chunk| hasher.update(chunk))?;
+            Ok(format!("{:032x}", hasher.digest128()))
+        }
+    }
+}
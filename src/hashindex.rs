@@ -0,0 +1,138 @@
+//! Remembering the hashes of destination files between runs, for --hash-index.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, bail};
+
+use crate::hash::{self, HashAlgorithm};
+
+/// Version of the index file format; an index in any other format is discarded.
+const FORMAT_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    size: u64,
+    /// Modification time, in nanoseconds since the Unix epoch.
+    mtime: u128,
+    hash: String,
+}
+
+/// Size and modification time, which must both match for a remembered hash to be trusted.
+fn stamp(path: &Path) -> io::Result<(u64, u128)> {
+    let meta = std::fs::metadata(path)?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    Ok((meta.len(), mtime))
+}
+
+/// Hashes of files under the destination, keyed by their path relative to it.
+pub struct HashIndex {
+    root: PathBuf,
+    algorithm: HashAlgorithm,
+    files: HashMap<String, Entry>,
+}
+
+impl HashIndex {
+    /// Load the index, or start an empty one if it doesn't exist yet, or was made with a
+    /// different hash algorithm or in another format.
+    pub fn load(path: &Path, root: &Path, algorithm: HashAlgorithm) -> anyhow::Result<Self> {
+        let mut index = Self {
+            root: root.to_owned(),
+            algorithm,
+            files: HashMap::new(),
+        };
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(index),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {path:?}")),
+        };
+        let value: serde_json::Value = serde_json::from_str(&json)
+            .with_context(|| format!("failed to parse {path:?}"))?;
+
+        if value["version"].as_u64() != Some(FORMAT_VERSION) {
+            eprintln!("hash index {path:?} is in an unknown format; rebuilding it");
+            return Ok(index);
+        }
+        if value["algorithm"].as_str() != Some(algorithm.name()) {
+            eprintln!("hash index {path:?} was made with a different hash algorithm; rebuilding it");
+            return Ok(index);
+        }
+        let Some(files) = value["files"].as_object() else {
+            bail!("{path:?} has no files");
+        };
+        for (name, entry) in files {
+            let size = entry["size"].as_u64();
+            // Too big for a JSON number without losing precision, so it's a string.
+            let mtime = entry["mtime"].as_str().and_then(|s| s.parse().ok());
+            let hash = entry["hash"].as_str();
+            if let (Some(size), Some(mtime), Some(hash)) = (size, mtime, hash) {
+                index.files.insert(name.clone(), Entry { size, mtime, hash: hash.to_owned() });
+            }
+        }
+        Ok(index)
+    }
+
+    /// Key for a file, if it's under the root and has a UTF-8 path; other files aren't indexed.
+    fn key(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.root).ok()?.to_str().map(str::to_owned)
+    }
+
+    /// The hash of a destination file: remembered, if the file hasn't changed since, or computed
+    /// (and remembered) otherwise.
+    pub fn hash(&mut self, path: &Path) -> io::Result<String> {
+        let (size, mtime) = stamp(path)?;
+        let key = self.key(path);
+        if let Some(entry) = key.as_ref().and_then(|k| self.files.get(k)) {
+            if entry.size == size && entry.mtime == mtime {
+                return Ok(entry.hash.clone());
+            }
+        }
+        let hash = hash::hash_file(path, self.algorithm)?;
+        if let Some(key) = key {
+            self.files.insert(key, Entry { size, mtime, hash: hash.clone() });
+        }
+        Ok(hash)
+    }
+
+    /// Remember the hash of a file just written to the destination.
+    pub fn insert(&mut self, path: &Path, hash: &str) {
+        let (Some(key), Ok((size, mtime))) = (self.key(path), stamp(path)) else {
+            return;
+        };
+        self.files.insert(key, Entry { size, mtime, hash: hash.to_owned() });
+    }
+
+    /// Write the index out, leaving out files that no longer exist.
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        let root = &self.root;
+        self.files.retain(|name, _| root.join(name).is_file());
+
+        let mut names = self.files.keys().collect::<Vec<_>>();
+        names.sort();
+        let files = names.into_iter()
+            .map(|name| {
+                let e = &self.files[name];
+                (name.clone(), serde_json::json!({
+                    "size": e.size,
+                    "mtime": e.mtime.to_string(),
+                    "hash": e.hash,
+                }))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let json = serde_json::json!({
+            "version": FORMAT_VERSION,
+            "algorithm": self.algorithm.name(),
+            "files": files,
+        });
+
+        // Write it next to the old one and then replace it, so a crash can't leave half an index.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, serde_json::to_string(&json)? + "\n")
+            .with_context(|| format!("failed to write {tmp:?}"))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {path:?}"))
+    }
+}
@@ -0,0 +1,80 @@
+//! Counting files by month, for --histogram.
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    files: u64,
+    bytes: u64,
+    /// Files dated only by their modification time.
+    from_mtime: u64,
+}
+
+/// Number and size of files copied in each month.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    months: BTreeMap<(u16, u8), Bucket>,
+}
+
+impl Histogram {
+    pub fn add(&mut self, year: u16, month: u8, bytes: u64, from_mtime: bool) {
+        let bucket = self.months.entry((year, month)).or_default();
+        bucket.files += 1;
+        bucket.bytes += bytes;
+        bucket.from_mtime += u64::from(from_mtime);
+    }
+
+    /// Print a table with a row for every month from the first to the last, so that months
+    /// with no files stand out, and a subtotal row after each year.
+    pub fn print(&self) {
+        for line in self.lines() {
+            eprintln!("{line}");
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let (Some(&first), Some(&last)) = (self.months.keys().next(), self.months.keys().next_back()) else {
+            return vec![];
+        };
+        let row = |label: &str, b: Bucket| format!("{label:<7} {:>8} {:>15} {:>8}", b.files, b.bytes, b.from_mtime);
+        let mut lines = vec![format!("{:<7} {:>8} {:>15} {:>8}", "month", "files", "bytes", "mtime")];
+        let (mut year, mut month) = first;
+        let mut subtotal = Bucket::default();
+        while (year, month) <= last {
+            let b = self.months.get(&(year, month)).copied().unwrap_or_default();
+            lines.push(row(&format!("{year:04}-{month:02}"), b));
+            subtotal.files += b.files;
+            subtotal.bytes += b.bytes;
+            subtotal.from_mtime += b.from_mtime;
+            if month == 12 || (year, month) == last {
+                lines.push(row(&format!("{year:04}"), subtotal));
+                subtotal = Bucket::default();
+            }
+            month += 1;
+            if month > 12 {
+                month = 1;
+                year += 1;
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtotal_after_each_year() {
+        let mut h = Histogram::default();
+        h.add(2019, 11, 100, false);
+        h.add(2019, 12, 10, true);
+        h.add(2019, 12, 1, false);
+        h.add(2020, 2, 1000, true);
+        let lines = h.lines();
+        let labels = lines.iter().map(|l| l.split_whitespace().next().unwrap()).collect::<Vec<_>>();
+        assert_eq!(labels, ["month", "2019-11", "2019-12", "2019", "2020-01", "2020-02", "2020"]);
+        assert_eq!(lines[3].split_whitespace().collect::<Vec<_>>(), ["2019", "3", "111", "1"]);
+        assert_eq!(lines[6].split_whitespace().collect::<Vec<_>>(), ["2020", "1", "1000", "1"]);
+    }
+}
@@ -0,0 +1,58 @@
+//! Gitignore-style patterns for files to leave alone, from `.cubackfillignore` at the top of the
+//! source tree and from --ignore-file.
+
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::Match;
+
+/// Name of the ignore file read from the top of the source tree.
+pub const FILE_NAME: &str = ".cubackfillignore";
+
+/// Load the patterns from the source tree's ignore file (if there is one) and from `extra`. The
+/// patterns in `extra` come last, so they win.
+pub fn load(src: &Path, extra: Option<&Path>) -> Result<Gitignore, ignore::Error> {
+    let mut builder = GitignoreBuilder::new(src);
+    let default = src.join(FILE_NAME);
+    if default.is_file() {
+        if let Some(e) = builder.add(&default) {
+            return Err(e);
+        }
+    }
+    if let Some(path) = extra {
+        if let Some(e) = builder.add(path) {
+            return Err(e);
+        }
+    }
+    builder.build()
+}
+
+/// Whether this is the source tree's own ignore file, which isn't a file to copy.
+pub fn is_ignore_file(src: &Path, path: &Path) -> bool {
+    path.parent() == Some(src) && path.file_name() == Some(FILE_NAME.as_ref())
+}
+
+/// The pattern that ignores `path`, which is in a directory that has already been checked.
+pub fn matched<'a>(ignore: &'a Gitignore, path: &Path, is_dir: bool) -> Option<&'a Glob> {
+    match ignore.matched(path, is_dir) {
+        Match::Ignore(glob) => Some(glob),
+        Match::None | Match::Whitelist(_) => None,
+    }
+}
+
+/// The last pattern that matches `path` or any of the directories it's in, which decides whether
+/// it's ignored. Paths outside the source tree never match.
+pub fn matched_with_parents<'a>(ignore: &'a Gitignore, path: &Path, is_dir: bool) -> Match<&'a Glob> {
+    if !path.starts_with(ignore.path()) {
+        return Match::None;
+    }
+    ignore.matched_path_or_any_parents(path, is_dir)
+}
+
+/// Where a pattern came from, for messages.
+pub fn describe(glob: &Glob) -> String {
+    match glob.from() {
+        Some(file) => format!("{:?} in {file:?}", glob.original()),
+        None => format!("{:?}", glob.original()),
+    }
+}
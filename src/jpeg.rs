@@ -0,0 +1,384 @@
+//! Digging out and rewriting the EXIF data of JPEG files.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+
+use anyhow::{Context, bail};
+use exif::experimental::Writer;
+use exif::{Context as TagContext, DateTime, Exif, Field, In, Reader, Tag, Value};
+
+const SOI: [u8; 2] = [0xFF, 0xD8];
+const APP0: [u8; 2] = [0xFF, 0xE0];
+const APP1: [u8; 2] = [0xFF, 0xE1];
+
+/// How much of the start of a file is searched by [`resync_exif`].
+const RESYNC_WINDOW: u64 = 1 << 20;
+
+/// Build an APP1 segment holding the EXIF data from a writer.
+fn app1_segment(mut writer: Writer<'_>) -> anyhow::Result<Vec<u8>> {
+    let mut tiff = Cursor::new(vec![]);
+    writer.write(&mut tiff, false).context("failed to write EXIF data")?;
+    let tiff = tiff.into_inner();
+
+    // The length counts itself, the "Exif\0\0" header, and the data.
+    let Ok(len) = u16::try_from(2 + 6 + tiff.len()) else {
+        bail!("EXIF data is too big for a JPEG segment ({} bytes)", tiff.len());
+    };
+    let mut segment = Vec::with_capacity(2 + usize::from(len));
+    segment.extend_from_slice(&APP1);
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(&tiff);
+    Ok(segment)
+}
+
+/// Where a new APP1 segment should go: right after the SOI marker, or after the JFIF APP0
+/// segment if there is one (which must come first).
+fn app1_position(jpeg: &[u8]) -> anyhow::Result<usize> {
+    if !jpeg.starts_with(&SOI) {
+        bail!("not a JPEG file");
+    }
+    if jpeg.get(2..4) == Some(&APP0[..]) {
+        let Some(len) = jpeg.get(4..6).map(|b| usize::from(u16::from_be_bytes([b[0], b[1]]))) else {
+            bail!("truncated JPEG file");
+        };
+        if jpeg.len() < 4 + len {
+            bail!("truncated JPEG file");
+        }
+        return Ok(4 + len);
+    }
+    Ok(2)
+}
+
+/// The segments before the image data of a JPEG, as (position, marker, total length).
+fn segments(jpeg: &[u8]) -> impl Iterator<Item = (usize, u8, usize)> + '_ {
+    let mut pos = SOI.len();
+    std::iter::from_fn(move || {
+        while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF {
+            let marker = jpeg[pos + 1];
+            match marker {
+                // Fill byte before a marker.
+                0xFF => {
+                    pos += 1;
+                    continue;
+                }
+                // Start of scan or end of image: no more metadata segments.
+                0xDA | 0xD9 => return None,
+                _ => (),
+            }
+            let len = usize::from(u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]));
+            if len < 2 || pos + 2 + len > jpeg.len() {
+                return None;
+            }
+            let start = pos;
+            pos += 2 + len;
+            return Some((start, marker, 2 + len));
+        }
+        None
+    })
+}
+
+/// The TIFF data of the segment, if it's an APP1 segment holding EXIF data.
+fn exif_payload(jpeg: &[u8], pos: usize, marker: u8, len: usize) -> Option<&[u8]> {
+    if marker != APP1[1] {
+        return None;
+    }
+    jpeg[pos + 4 .. pos + len].strip_prefix(b"Exif\0\0")
+}
+
+/// Look harder for a date in a JPEG whose EXIF data couldn't be read the normal way: skip any
+/// junk before the start-of-image marker, and try every EXIF APP1 segment instead of just the
+/// first, until one has a DateTimeOriginal tag.
+pub fn resync_exif(mut file: &File) -> std::io::Result<Option<Exif>> {
+    let mut data = vec![];
+    file.rewind()?;
+    file.take(RESYNC_WINDOW).read_to_end(&mut data)?;
+
+    let starts = data.windows(3)
+        .enumerate()
+        .filter(|(_, w)| w[..2] == SOI && w[2] == 0xFF)
+        .map(|(i, _)| i);
+    for start in starts {
+        let jpeg = &data[start..];
+        for (pos, marker, len) in segments(jpeg) {
+            let Some(tiff) = exif_payload(jpeg, pos, marker, len) else {
+                continue;
+            };
+            if let Ok(exif) = Reader::new().read_raw(tiff.to_vec()) {
+                if exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some() {
+                    return Ok(Some(exif));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the EXIF data has any GPS tags.
+pub fn has_gps(exif: &Exif) -> bool {
+    exif.fields().any(|f| f.tag.context() == TagContext::Gps)
+}
+
+/// Size in bytes of one value of each TIFF field type, indexed by type.
+const TYPE_SIZES: [usize; 13] = [0, 1, 1, 2, 4, 8, 1, 1, 2, 4, 8, 4, 8];
+
+/// The header of an APP1 segment holding XMP data.
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Remove the location from a JPEG file, in place, returning `None` if it had none. In the EXIF
+/// data, the GPS IFD and everything its entries point to are zeroed, and the pointer to it is
+/// removed from IFD0. In the XMP data, the GPS properties are blanked out with spaces.
+///
+/// Nothing moves, so data found by offset (like maker notes, or the later images of an MPO file)
+/// stays valid. Every image in the file is done, not just the first.
+pub fn strip_gps(jpeg: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut out = jpeg.to_vec();
+    let mut changed = false;
+    let mut done = std::collections::HashSet::new();
+    let starts = jpeg.windows(3)
+        .enumerate()
+        .filter(|(_, w)| w[..2] == SOI && w[2] == 0xFF)
+        .map(|(i, _)| i);
+    for start in starts {
+        for (pos, marker, len) in segments(&jpeg[start..]) {
+            let pos = start + pos;
+            if marker != APP1[1] || !done.insert(pos) {
+                continue;
+            }
+            // Skip the marker and the length.
+            let payload = &mut out[pos + 4 .. pos + len];
+            if let Some(tiff) = payload.strip_prefix(b"Exif\0\0") {
+                let header = payload.len() - tiff.len();
+                changed |= strip_gps_ifd(&mut payload[header..]).context("malformed EXIF data")?;
+            } else if payload.starts_with(XMP_HEADER) {
+                changed |= blank_xmp_gps(&mut payload[XMP_HEADER.len()..]);
+            }
+        }
+    }
+    Ok(changed.then_some(out))
+}
+
+/// Whether a byte can be part of an XML name.
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.') || b >= 0x80
+}
+
+/// Blank out the GPS properties (like `exif:GPSLatitude`) of an XMP packet with spaces, whether
+/// they're written as attributes or as elements, returning whether there were any.
+fn blank_xmp_gps(xmp: &mut [u8]) -> bool {
+    let find = |xmp: &[u8], from: usize, what: &[u8]| {
+        xmp.get(from..)?.windows(what.len()).position(|w| w == what).map(|i| from + i)
+    };
+    let mut changed = false;
+    let mut from = 0;
+    while let Some(colon) = find(xmp, from, b":GPS") {
+        from = colon + 1;
+        let start = xmp[..colon].iter().rposition(|&b| !is_name_byte(b)).map_or(0, |i| i + 1);
+        let end = xmp[colon + 1 ..].iter().position(|&b| !is_name_byte(b)).map_or(xmp.len(), |i| colon + 1 + i);
+        let name = xmp[start..end].to_vec();
+        let blank = match start.checked_sub(1).map(|i| xmp[i]) {
+            // An element, which ends either right away or at its end tag.
+            Some(b'<') => find(xmp, end, b">").and_then(|gt| {
+                if xmp[gt - 1] == b'/' {
+                    return Some((start - 1, gt + 1));
+                }
+                let close = [b"</", &name[..], b">"].concat();
+                find(xmp, gt, &close).map(|at| (start - 1, at + close.len()))
+            }),
+            // An attribute: the name, "=", and the quoted value.
+            Some(b) if b.is_ascii_whitespace() => {
+                let skip_space = |at: usize| at + xmp[at..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+                let eq = skip_space(end);
+                if xmp.get(eq) == Some(&b'=') {
+                    let quote = skip_space(eq + 1);
+                    match xmp.get(quote) {
+                        Some(&q @ (b'"' | b'\'')) => find(xmp, quote + 1, &[q]).map(|at| (start, at + 1)),
+                        _ => None,
+                    }
+                } else {
+                    None
+                }
+            }
+            // End tags were dealt with along with their elements.
+            _ => None,
+        };
+        if let Some((blank_start, blank_end)) = blank {
+            xmp[blank_start..blank_end].fill(b' ');
+            changed = true;
+            from = blank_end;
+        }
+    }
+    changed
+}
+
+/// Remove the GPS IFD of some EXIF data, returning whether it had one.
+fn strip_gps_ifd(tiff: &mut [u8]) -> anyhow::Result<bool> {
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => bail!("bad byte order"),
+    };
+    let u16_at = |tiff: &[u8], at: usize| -> anyhow::Result<usize> {
+        let b = tiff.get(at..at + 2).context("IFD out of bounds")?;
+        let n = if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) };
+        Ok(n.into())
+    };
+    let u32_at = |tiff: &[u8], at: usize| -> anyhow::Result<usize> {
+        let b = tiff.get(at..at + 4).context("IFD out of bounds")?;
+        let b = [b[0], b[1], b[2], b[3]];
+        let n = if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) };
+        Ok(usize::try_from(n)?)
+    };
+
+    let ifd0 = u32_at(tiff, 4)?;
+    let count = u16_at(tiff, ifd0)?;
+    let entries = ifd0 + 2;
+    let end = entries + 12 * count + 4;
+    if end > tiff.len() {
+        bail!("IFD0 out of bounds");
+    }
+    let Some(index) = (0..count).find(|i| u16_at(tiff, entries + 12 * i).ok() == Some(Tag::GPSInfoIFDPointer.number().into())) else {
+        return Ok(false);
+    };
+    let gps = u32_at(tiff, entries + 12 * index + 8)?;
+
+    // Zero the values of the GPS entries that are stored elsewhere, then the entries themselves,
+    // which leaves an empty IFD.
+    let gps_count = u16_at(tiff, gps)?;
+    let gps_end = gps.checked_add(2 + 12 * gps_count + 4).filter(|&e| e <= tiff.len()).context("GPS IFD out of bounds")?;
+    for i in 0..gps_count {
+        let entry = gps + 2 + 12 * i;
+        let size = TYPE_SIZES.get(u16_at(tiff, entry + 2)?).copied().unwrap_or(0)
+            .checked_mul(u32_at(tiff, entry + 4)?)
+            .context("GPS value too big")?;
+        if size > 4 {
+            let offset = u32_at(tiff, entry + 8)?;
+            let value = offset.checked_add(size).and_then(|e| tiff.get_mut(offset..e));
+            value.context("GPS value out of bounds")?.fill(0);
+        }
+    }
+    tiff[gps..gps_end].fill(0);
+
+    // Remove the pointer by moving the entries after it (and the next IFD offset) up.
+    let at = entries + 12 * index;
+    tiff.copy_within(at + 12 .. end, at);
+    tiff[end - 12 .. end].fill(0);
+    let count = u16::try_from(count - 1)?;
+    let count = if big_endian { count.to_be_bytes() } else { count.to_le_bytes() };
+    tiff[ifd0..ifd0 + 2].copy_from_slice(&count);
+    Ok(true)
+}
+
+/// Add EXIF data with just a DateTimeOriginal tag to a JPEG file that has no EXIF data.
+pub fn embed_date(jpeg: &[u8], dt: &DateTime) -> anyhow::Result<Vec<u8>> {
+    let value = format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second);
+    let field = Field {
+        tag: Tag::DateTimeOriginal,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.into_bytes()]),
+    };
+    let mut writer = Writer::new();
+    writer.push_field(&field);
+    let segment = app1_segment(writer)?;
+
+    let pos = app1_position(jpeg)?;
+    let mut out = Vec::with_capacity(jpeg.len() + segment.len());
+    out.extend_from_slice(&jpeg[..pos]);
+    out.extend_from_slice(&segment);
+    out.extend_from_slice(&jpeg[pos..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn fixture_path(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(fixture_path(name)).unwrap()
+    }
+
+    fn resynced_date(name: &str) -> String {
+        let file = File::open(fixture_path(name)).unwrap();
+        let exif = resync_exif(&file).unwrap().expect("no EXIF found");
+        let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).unwrap();
+        field.display_value().to_string()
+    }
+
+    fn read(jpeg: &[u8]) -> Exif {
+        Reader::new().read_from_container(&mut Cursor::new(jpeg)).unwrap()
+    }
+
+    #[test]
+    fn strip_gps_leaves_everything_else_in_place() {
+        let jpeg = fixture("gps.jpg");
+        assert!(has_gps(&read(&jpeg)));
+
+        let stripped = strip_gps(&jpeg).unwrap().expect("nothing stripped");
+        assert_eq!(stripped.len(), jpeg.len());
+        let exif = read(&stripped);
+        assert!(!has_gps(&exif));
+        assert!(exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some());
+
+        // The maker note is where it was, byte for byte.
+        let maker = exif.get_field(Tag::MakerNote, In::PRIMARY).unwrap();
+        let Value::Undefined(ref data, offset) = maker.value else {
+            panic!("unexpected maker note value {:?}", maker.value);
+        };
+        assert!(data.starts_with(b"MAKERNOTE"));
+        let original = read(&jpeg);
+        let Value::Undefined(_, original_offset) = original.get_field(Tag::MakerNote, In::PRIMARY).unwrap().value else {
+            unreachable!();
+        };
+        assert_eq!(offset, original_offset);
+
+        // The coordinates are gone from the file, not just unreferenced.
+        let latitude = [35u32, 1, 41, 1, 1234, 100].iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<_>>();
+        assert!(jpeg.windows(latitude.len()).any(|w| w == latitude));
+        assert!(!stripped.windows(latitude.len()).any(|w| w == latitude));
+    }
+
+    #[test]
+    fn strip_gps_blanks_xmp() {
+        let jpeg = fixture("xmp-gps.jpg");
+        let stripped = strip_gps(&jpeg).unwrap().expect("nothing stripped");
+        assert_eq!(stripped.len(), jpeg.len());
+        let text = String::from_utf8_lossy(&stripped);
+        assert!(!text.contains("GPS"), "{text}");
+        assert!(!text.contains("35,41.2N") && !text.contains("139,41.5E") && !text.contains("1234/100"));
+        assert!(text.contains(r#"xmp:CreatorTool="TestTool">"#));
+        assert!(text.contains("<xmp:Rating>3</xmp:Rating>"));
+        assert!(read(&stripped).get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some());
+    }
+
+    #[test]
+    fn strip_gps_does_every_image_of_an_mpo() {
+        let mpo = [fixture("gps.jpg"), fixture("gps.jpg")].concat();
+        let stripped = strip_gps(&mpo).unwrap().expect("nothing stripped");
+        let latitude = [35u32, 1, 41, 1, 1234, 100].iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<_>>();
+        assert!(!stripped.windows(latitude.len()).any(|w| w == latitude));
+        assert!(!has_gps(&read(&stripped[mpo.len() / 2 ..])));
+    }
+
+    #[test]
+    fn strip_gps_without_any() {
+        let stripped = strip_gps(&fixture("gps.jpg")).unwrap().expect("nothing stripped");
+        assert!(strip_gps(&stripped).unwrap().is_none());
+    }
+
+    #[test]
+    fn resync_skips_junk_before_soi() {
+        assert!(Reader::new().read_from_container(&mut Cursor::new(fixture("junk-before-soi.jpg"))).is_err());
+        assert_eq!(resynced_date("junk-before-soi.jpg"), "2019-04-02 10:11:12");
+    }
+
+    #[test]
+    fn resync_skips_xmp_before_exif() {
+        assert_eq!(resynced_date("xmp-before-exif.jpg"), "2019-04-02 10:11:12");
+    }
+}
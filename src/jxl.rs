@@ -0,0 +1,39 @@
+//! Finding the EXIF data of JPEG XL images.
+
+use std::io::{self, Read, Seek};
+
+use crate::bmff;
+
+/// The start of a JPEG XL file in the ISO base media container format.
+const CONTAINER_SIGNATURE: [u8; 12] = [0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+
+/// The start of a bare JPEG XL codestream, which has no room for metadata.
+const CODESTREAM_SIGNATURE: [u8; 2] = [0xFF, 0x0A];
+
+/// Largest Exif box that will be read into memory.
+const MAX_EXIF_LEN: u64 = 16 << 20;
+
+/// The TIFF data of the Exif box of a JPEG XL image, if it has one.
+///
+/// Brotli-compressed (`brob`) metadata boxes aren't supported, and count as no EXIF data.
+pub fn exif_box<R: Read + Seek>(mut r: R) -> io::Result<Option<Vec<u8>>> {
+    let mut signature = [0u8; CONTAINER_SIGNATURE.len()];
+    let n = r.read(&mut signature)?;
+    if signature[..n].starts_with(&CODESTREAM_SIGNATURE) {
+        return Ok(None);
+    }
+    if signature != CONTAINER_SIGNATURE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a JPEG XL file"));
+    }
+
+    let Some((start, len)) = bmff::find_path(&mut r, &[b"Exif"])? else {
+        return Ok(None);
+    };
+    if len > MAX_EXIF_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Exif box is implausibly large"));
+    }
+    r.seek(io::SeekFrom::Start(start))?;
+    let mut data = vec![0u8; len as usize];
+    r.read_exact(&mut data)?;
+    Ok(bmff::exif_tiff(&data).map(<[u8]>::to_vec))
+}
@@ -0,0 +1,39 @@
+//! Converting absolute points in time to the wall-clock time used for naming.
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+
+/// Convert a point in time to wall-clock time in the given zone.
+///
+/// Going this way is never ambiguous: every instant has exactly one offset in a zone, even
+/// around daylight saving time transitions (only the reverse conversion can hit a gap or a
+/// repeated hour).
+pub fn to_local<Z: TimeZone>(tz: &Z, utc: &chrono::DateTime<Utc>) -> NaiveDateTime {
+    utc.with_timezone(tz).naive_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::Los_Angeles;
+
+    fn local(utc: &str) -> String {
+        let utc = utc.parse::<chrono::DateTime<Utc>>().unwrap();
+        to_local(&Los_Angeles, &utc).to_string()
+    }
+
+    #[test]
+    fn spring_forward() {
+        // 2021-03-14: clocks jump from 02:00 PST to 03:00 PDT.
+        assert_eq!(local("2021-03-14T09:59:59Z"), "2021-03-14 01:59:59");
+        assert_eq!(local("2021-03-14T10:00:00Z"), "2021-03-14 03:00:00");
+    }
+
+    #[test]
+    fn fall_back() {
+        // 2021-11-07: clocks go back from 02:00 PDT to 01:00 PST, so 01:30 happens twice.
+        assert_eq!(local("2021-11-07T08:30:00Z"), "2021-11-07 01:30:00");
+        assert_eq!(local("2021-11-07T08:59:59Z"), "2021-11-07 01:59:59");
+        assert_eq!(local("2021-11-07T09:00:00Z"), "2021-11-07 01:00:00");
+        assert_eq!(local("2021-11-07T09:30:00Z"), "2021-11-07 01:30:00");
+    }
+}
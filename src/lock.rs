@@ -0,0 +1,70 @@
+//! Locking the destination directory, so that two runs can't write into it at the same time.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+
+const LOCK_FILE_NAME: &str = ".cu_backfill.lock";
+
+/// An advisory lock on a destination directory, held until this is dropped (or the process
+/// exits, however that happens).
+pub struct DstLock {
+    file: File,
+}
+
+/// Whether this is a lock file, which should never be treated as a photo.
+pub fn is_lock_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == LOCK_FILE_NAME)
+}
+
+/// Lock the destination directory, creating it if necessary.
+pub fn acquire(dst: &Path) -> anyhow::Result<DstLock> {
+    std::fs::create_dir_all(dst)
+        .with_context(|| format!("failed to create destination directory {dst:?}"))?;
+
+    let path = dst.join(LOCK_FILE_NAME);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file {path:?}"))?;
+
+    let mut pid = String::new();
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => {
+            let _ = file.read_to_string(&mut pid);
+            bail!("another run (pid {}) is already using {dst:?}; use --no-lock to run anyway",
+                pid.trim());
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("failed to lock {path:?}"));
+        }
+    }
+
+    // We hold the lock now, so any pid left in the file belongs to a process that's gone.
+    file.read_to_string(&mut pid)
+        .with_context(|| format!("failed to read lock file {path:?}"))?;
+    if !pid.trim().is_empty() {
+        eprintln!("taking over stale lock {path:?} left by pid {}", pid.trim());
+    }
+
+    file.set_len(0)
+        .and_then(|()| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| writeln!(file, "{}", std::process::id()))
+        .with_context(|| format!("failed to write lock file {path:?}"))?;
+
+    Ok(DstLock { file })
+}
+
+impl Drop for DstLock {
+    fn drop(&mut self) {
+        // Leave the file in place (removing it would race with another process opening it), but
+        // clear the pid so it doesn't look stale. Closing the file releases the lock.
+        let _ = self.file.set_len(0);
+    }
+}
@@ -1,55 +1,873 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::BufReader; use std::path::PathBuf;
+use std::io::{BufReader, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use anyhow::{Context, anyhow, bail};
 use chrono::{Datelike, Timelike};
-use clap::Parser;
-use exif::{DateTime, In, Reader, Value, Tag};
-use walkdir::WalkDir;
+use chrono_tz::Tz;
+use clap::{Parser, ValueEnum};
+use exif::{DateTime, Exif, In, Reader, Value, Tag};
+use unicode_normalization::UnicodeNormalization;
+
+mod bmff;
+mod clips;
+mod diff;
+mod duplicates;
+mod ebml;
+mod exiftool;
+mod fat;
+mod filelist;
+mod filename;
+mod gps;
+mod hash;
+mod hashindex;
+mod histogram;
+mod ignorefile;
+mod jpeg;
+mod jxl;
+mod localtime;
+mod lock;
+mod marker;
+mod origin;
+mod owner;
+mod preflight;
+mod prune;
+mod rawpath;
+mod script;
+mod space;
+mod stats;
+mod status;
+mod taken;
+mod takeout;
+mod watch;
+
+use diff::Diff;
+use duplicates::DuplicateReport;
+use fat::Fat;
+use hash::HashAlgorithm;
+use hashindex::HashIndex;
+use histogram::Histogram;
+use origin::RecordOrigin;
+use owner::Owner;
+use script::{Script, ScriptFormat};
+use space::FreeSpace;
+use stats::{Stage, Stats};
+use status::Status;
+use taken::Taken;
 
 /// Copy all files from a directory tree into another, using names that match how Dropbox Camera
 /// Uploads would rename them (additionally split up by year).
 ///
-/// Date and time of files is taken from file metadata (EXIF tags) if possible, or file
-/// modification otherwise.
+/// Date and time of files is taken from a Google Takeout JSON file next to it or from file
+/// metadata (EXIF tags) if possible, then from a date embedded in the file name, or file
+/// modification time otherwise.
+///
+/// Run `cu_backfill where <file>` to look up where a copied file came from (see --record-origin).
+///
+/// On Unix, send SIGUSR1 to print how far along the run is, and SIGUSR2 to pause after the current
+/// file (and again to resume).
 #[derive(Debug, Parser)]
+#[command(group(clap::ArgGroup::new("report").args(["diff", "verify_only"])))]
 struct Args {
     /// Path to copy files from. This tree is walked recursively.
     #[arg(long)]
     src: PathBuf,
 
+    /// Process the files listed in this file (or stdin, if "-"), one per line, instead of walking
+    /// --src. Relative paths are relative to --src.
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// With --files-from, the list is separated by NUL bytes instead of newlines.
+    #[arg(long, requires = "files_from")]
+    from0: bool,
+
+    /// After the initial pass, keep watching --src and process new or changed files once they
+    /// stop changing. Stop with Ctrl-C.
+    #[arg(long, conflicts_with = "files_from")]
+    watch: bool,
+
+    /// With --watch, how long (in seconds) a file must go unchanged before it is processed.
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    watch_debounce: u64,
+
     /// Path to copy the files to. A subdirectory under this will be added for each year.
     #[arg(long)]
     dst: PathBuf,
 
+    /// Skip any directory with this name, anywhere in the source tree. May be given multiple
+    /// times.
+    #[arg(long, value_name = "NAME")]
+    exclude_dir: Vec<String>,
+
+    /// Don't skip the thumbnail and metadata directories made by NAS boxes and photo managers
+    /// (@eaDir, .@__thumb, .thumbnails, __MACOSX), which are skipped by default.
+    #[arg(long)]
+    no_default_excludes: bool,
+
+    /// Read gitignore-style patterns of files and directories to skip from this file, in addition
+    /// to the .cubackfillignore file at the top of --src (if there is one). Directories skipped by
+    /// --exclude-dir or by default are skipped first, and can't be brought back by a pattern.
+    #[arg(long, value_name = "FILE")]
+    ignore_file: Option<PathBuf>,
+
+    /// Explain whether this path (relative to --src) is skipped by --exclude-dir or the ignore
+    /// patterns, and which rule decided it, then exit.
+    #[arg(long, value_name = "PATH")]
+    why: Option<PathBuf>,
+
+    /// Treat metadata that is present but can't be parsed (e.g. corrupt EXIF) as an error and skip
+    /// the file, instead of falling back to other date sources. With --strict=all, files with no
+    /// date in their metadata at all are errors too.
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "corrupt")]
+    strict: Option<Strict>,
+
+    /// When no date can be found in a file's metadata, ask exiftool (at this path, or found on
+    /// PATH) before falling back to the file name or modification time.
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true, default_missing_value = "exiftool")]
+    exiftool: Option<PathBuf>,
+
+    /// How long (in seconds) exiftool may take on one file before it's given up on.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    exiftool_timeout: u64,
+
+    /// What to do with EXIF dates that look wrong: a camera's reset default (midnight on January
+    /// 1st), before --date-floor, or in the future.
+    #[arg(long, value_enum, default_value_t = SuspectDates::Warn)]
+    suspect_dates: SuspectDates,
+
+    /// EXIF dates before this year are considered wrong (see --suspect-dates).
+    #[arg(long, value_name = "YEAR", default_value_t = 1990)]
+    date_floor: u16,
+
+    /// How to choose among the dates a file has.
+    #[arg(long, value_enum, default_value_t = DateStrategy::Priority)]
+    date_strategy: DateStrategy,
+
+    /// Print more details about what is being done.
+    #[arg(short, long)]
+    verbose: bool,
+
     /// Don't actually copy, just display what would be copied.
     #[arg(long)]
     dry_run: bool,
+
+    /// Instead of copying anything, write a script to this file that does the copying (implies
+    /// --dry-run).
+    #[arg(long, value_name = "FILE")]
+    emit_script: Option<PathBuf>,
+
+    /// The kind of script to write for --emit-script.
+    #[arg(long, value_enum, default_value_t = ScriptFormat::Sh)]
+    emit_script_format: ScriptFormat,
+
+    /// Instead of listing what would be copied, sort every file into new, already in the
+    /// destination (with identical contents), or conflicting with a different file of the same
+    /// name, and print how many there are of each (implies --dry-run). With --verbose, the files
+    /// in each group are listed too.
+    #[arg(long)]
+    diff: bool,
+
+    /// Check that every file is already in the destination, under the name a real run would give
+    /// it and with identical contents (or only the same size, with --skip-same=name-size), and
+    /// report the ones that are missing or different. Nothing is written (implies --dry-run). The
+    /// exit status is a failure unless every file is present.
+    #[arg(long)]
+    verify_only: bool,
+
+    /// With --verify-only, also report files in the destination that no source file accounts
+    /// for.
+    #[arg(long, requires = "verify_only")]
+    verify_extra: bool,
+
+    /// With --diff, write the files in each group to new.txt, duplicate.txt, and conflict.txt in
+    /// this directory. With --verify-only, they are missing.txt, present.txt, mismatched.txt, and
+    /// extra.txt.
+    #[arg(long, value_name = "DIR", requires = "report")]
+    diff_files: Option<PathBuf>,
+
+    /// Write a "<source>\0<destination>\0" record to stdout for each file copied (or that would be
+    /// copied, with --dry-run), for consumption by other programs, instead of the plan.
+    #[arg(long)]
+    print0: bool,
+
+    /// Move files instead of copying them.
+    #[arg(long = "move")]
+    move_files: bool,
+
+    /// After a --move run, remove source directories that were left empty (never --src itself).
+    #[arg(long, requires = "move_files")]
+    prune_empty_dirs: bool,
+
+    /// Skip files whose contents are identical to a file already at one of their destination
+    /// names.
+    #[arg(long)]
+    dedupe: bool,
+
+    /// How to tell apart files that would get the same name.
+    #[arg(long, value_enum, default_value_t = Suffix::Counter)]
+    suffix: Suffix,
+
+    /// Skip files that look like they were already copied, without comparing contents: a file
+    /// is already at one of its destination names, and the chosen attributes match.
+    #[arg(long, value_enum, value_name = "ATTRIBUTES")]
+    skip_same: Option<SkipSame>,
+
+    /// Move duplicate files (implies --dedupe) from the source into this directory, keeping their
+    /// path relative to --src, so they can be reviewed and deleted.
+    #[arg(long, requires = "move_files")]
+    duplicates_to: Option<PathBuf>,
+
+    /// Hash algorithm used for comparing file contents.
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Blake3)]
+    hash: HashAlgorithm,
+
+    /// Remember the hashes of destination files in this file, so later runs only hash files that
+    /// changed (by size or modification time) since.
+    #[arg(long, value_name = "FILE")]
+    hash_index: Option<PathBuf>,
+
+    /// Write a report of groups of source files with identical contents (including any identical
+    /// destination file) to this file. Written as JSON if the name ends in ".json".
+    #[arg(long)]
+    report_duplicates: Option<PathBuf>,
+
+    /// Also copy empty (zero-byte) files, which are skipped by default.
+    #[arg(long)]
+    keep_empty: bool,
+
+    /// Skip files modified less than this many seconds ago, which may still be being written.
+    #[arg(long, value_name = "SECONDS")]
+    min_age: Option<u64>,
+
+    /// Stop the run (cleanly) when the destination has less than this much free space left, e.g.
+    /// "5G". Checked before each copy.
+    #[arg(long, value_name = "SIZE", value_parser = space::parse_size)]
+    min_free: Option<u64>,
+
+    /// Stop the run (cleanly) once this many files have failed. 0 means no limit.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_errors: usize,
+
+    /// Stop the run if a directory in the source tree can't be read, instead of reporting it and
+    /// going on with the rest of the tree.
+    #[arg(long)]
+    halt_on_walk_error: bool,
+
+    /// Don't lock the destination directory against other runs using it at the same time.
+    #[arg(long)]
+    no_lock: bool,
+
+    /// For files already named in Camera Uploads style (e.g. from a previous run), keep the
+    /// existing name (including any "-N" suffix) instead of deriving a new one.
+    #[arg(long)]
+    keep_cu_names: bool,
+
+    /// Add a subdirectory under each year for the camera (EXIF Make and Model) that took the
+    /// file.
+    #[arg(long)]
+    by_camera: bool,
+
+    /// With --by-camera, where to put files that don't say which camera took them.
+    #[arg(long, value_enum, default_value_t = UnknownCamera::Folder)]
+    unknown_camera: UnknownCamera,
+
+    /// Under each year (and camera), recreate the directories the file was in, relative to
+    /// --src. Excluded directories are left out of the path.
+    #[arg(long)]
+    keep_structure: bool,
+
+    /// Only process files taken by a camera whose EXIF Make and Model contain this text (case
+    /// insensitive). May be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    camera: Vec<String>,
+
+    /// Skip files taken by a camera whose EXIF Make and Model contain this text (case
+    /// insensitive). May be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    exclude_camera: Vec<String>,
+
+    /// With --camera or --exclude-camera, whether to process files that don't say which camera
+    /// took them.
+    #[arg(long, value_enum, default_value_t = CameraUnknown::Include)]
+    camera_unknown: CameraUnknown,
+
+    /// Add the file's original name to the generated one, as in
+    /// "2016-04-11 09.15.30 (IMG_4572).jpg".
+    #[arg(long)]
+    append_original_name: bool,
+
+    /// For JPEG files with no EXIF data, whose date came from their name or modification time,
+    /// add EXIF data with that date to the copy. The source file is never modified.
+    #[arg(long)]
+    embed_date: bool,
+
+    /// Remove GPS location tags from the EXIF and XMP data of copied JPEG and MPO files. The
+    /// source file is never modified. Files that can't be rewritten are copied unmodified, or
+    /// skipped with --strict.
+    #[arg(long)]
+    strip_gps: bool,
+
+    /// Don't copy the low-res proxy (.LRV), thumbnail (.THM), and telemetry (.SRT) files that go
+    /// with GoPro and DJI videos. By default they are copied next to their video, under the same
+    /// name.
+    #[arg(long)]
+    skip_video_sidecars: bool,
+
+    /// Stay within the limits of a FAT32 or exFAT destination (e.g. an SD card): files too big
+    /// for FAT32 are skipped, and characters FAT can't store are replaced in names. Turned on
+    /// automatically when the destination is detected to be FAT32 or exFAT.
+    #[arg(long)]
+    fat_safe: bool,
+
+    /// Give copied files, and the directories created for them, to this user (and group), e.g.
+    /// "media:media". Unix only.
+    #[arg(long, value_name = "USER[:GROUP]", value_parser = owner::parse)]
+    chown: Option<Owner>,
+
+    /// Record on each copied file the path it was copied from and where its date came from.
+    #[arg(long, value_enum, default_value_t = RecordOrigin::None)]
+    record_origin: RecordOrigin,
+
+    /// After copying a file, mark the source file as processed (in an extended attribute, so Unix
+    /// only), and skip files that are already marked.
+    #[arg(long, conflicts_with = "move_files")]
+    mark_processed: bool,
+
+    /// With --mark-processed, process marked files anyway (and mark them again).
+    #[arg(long, requires = "mark_processed")]
+    ignore_markers: bool,
+
+    /// Remove the marks left by --mark-processed from the source files, and do nothing else.
+    #[arg(long, conflicts_with = "mark_processed")]
+    clear_markers: bool,
+
+    /// Time zone (IANA name, e.g. "America/Los_Angeles") to use for dates that are stored as an
+    /// absolute point in time, like file modification times. Defaults to the system's time zone.
+    #[arg(long)]
+    timezone: Option<Tz>,
+
+    /// For files with GPS coordinates in their EXIF data, use the time zone at that location
+    /// instead of --timezone, and use it for EXIF dates that record an absolute time (GPS time
+    /// stamps, or an offset from UTC).
+    #[arg(long)]
+    tz_from_gps: bool,
+
+    /// At the end, print how much time was spent in each stage of the run.
+    #[arg(long)]
+    stats: bool,
+
+    /// At the end, print how many files (and bytes) were copied for each month, and how many of
+    /// them were dated only by their modification time.
+    #[arg(long)]
+    histogram: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Strict {
+    /// Only metadata that is present but can't be parsed is an error.
+    Corrupt,
+    /// Having no date in the metadata is an error too.
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SuspectDates {
+    /// Use the date anyway, but warn about it.
+    Warn,
+    /// Ignore the date and use the next date source instead.
+    Fallback,
+    /// Use the date without checking it.
+    Keep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DateStrategy {
+    /// Use the first date found, in order: metadata, file name, modification time.
+    Priority,
+    /// Use the earliest of all the dates found (EXIF date tags, video creation time, file name,
+    /// creation and modification times), leaving out any that look wrong.
+    Earliest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Suffix {
+    /// Add "-1", "-2", and so on, in the order files are processed.
+    Counter,
+    /// Add the first 8 hex digits of the file's hash, so the name depends only on the file.
+    Hash,
+}
+
+/// What --skip-same compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SkipSame {
+    /// Only that the name exists.
+    Name,
+    /// The name and size.
+    NameSize,
+    /// The name and size, and that the destination is no older than the source.
+    NameSizeMtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CameraUnknown {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum UnknownCamera {
+    /// In an "Unknown" subdirectory.
+    Folder,
+    /// Directly in the year directory.
+    Root,
 }
 
-fn exif_datetime(file: &File) -> anyhow::Result<DateTime> {
-    let exif = Reader::new()
+fn read_exif(file: &File, ext: &str) -> anyhow::Result<Exif> {
+    if ext == "jxl" {
+        // The exif crate doesn't know about JPEG XL.
+        let tiff = jxl::exif_box(BufReader::new(file))
+            .context("failed to read exif")?
+            .ok_or(exif::Error::NotFound("JPEG XL"))?;
+        return Reader::new().read_raw(tiff).context("failed to read exif");
+    }
+    Reader::new()
         .read_from_container(&mut BufReader::new(file))
-        .context("failed to read exif")?;
+        .context("failed to read exif")
+}
+
+/// Read the EXIF data of a HEIF or AVIF image from its Exif item directly.
+fn heif_exif(mut file: &File) -> std::io::Result<Option<Exif>> {
+    file.rewind()?;
+    let Some(tiff) = bmff::exif_item(BufReader::new(file))? else {
+        return Ok(None);
+    };
+    Ok(Reader::new().read_raw(tiff).ok())
+}
+
+/// Whether an error from [`read_exif`] just means the file has no EXIF data.
+fn is_missing_exif(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<exif::Error>(), Some(exif::Error::NotFound(_)))
+}
 
-    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
-        .ok_or_else(|| anyhow!("no DateTimeOriginal EXIF tag found"))?;
+/// Get the DateTimeOriginal tag, if there is one.
+fn exif_datetime(exif: &Exif) -> anyhow::Result<Option<DateTime>> {
+    exif_tag_datetime(exif, Tag::DateTimeOriginal)
+}
+
+/// Get a date tag, if there is one.
+fn exif_tag_datetime(exif: &Exif, tag: Tag) -> anyhow::Result<Option<DateTime>> {
+    let Some(field) = exif.get_field(tag, In::PRIMARY) else {
+        return Ok(None);
+    };
 
     let value = match field.value {
         Value::Ascii(ref vec) if !vec.is_empty() => &vec[0],
-        _ => bail!("DateTimeOriginal EXIF tag has non-ASCII value: {:?}", field.value),
+        _ => bail!("{tag} EXIF tag has non-ASCII value: {:?}", field.value),
     };
 
     let dt = DateTime::from_ascii(&value[..])
-        .with_context(|| format!("unable to parse EXIF DateTime {value:?}"))?;
+        .with_context(|| format!("unable to parse EXIF {tag} {value:?}"))?;
+
+    Ok(Some(dt))
+}
+
+/// Order dates by when they are, which [`DateTime`] itself doesn't do.
+fn date_key(dt: &DateTime) -> (u16, u8, u8, u8, u8, u8) {
+    (dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second)
+}
+
+/// Directories that only ever contain derived copies (thumbnails, previews, resource forks) of
+/// files elsewhere, which are skipped unless --no-default-excludes is given.
+const DEFAULT_EXCLUDE_DIRS: &[&str] = &[
+    "@eaDir",      // Synology
+    ".@__thumb",   // QNAP
+    ".thumbnails", // digiKam and others
+    "__MACOSX",    // macOS zip archives
+];
+
+/// Where a file's date and time were taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateSource {
+    Exif,
+    /// The creation time in a video's container metadata.
+    Video,
+    /// A date tag found by exiftool.
+    Exiftool,
+    /// The photo taken time in a Google Takeout JSON file.
+    TakeoutJson,
+    Filename,
+    /// The file's creation (birth) time.
+    Btime,
+    Mtime,
+}
+
+impl DateSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            DateSource::Exif => "exif",
+            DateSource::Video => "video",
+            DateSource::Exiftool => "exiftool",
+            DateSource::TakeoutJson => "takeout-json",
+            DateSource::Filename => "filename",
+            DateSource::Btime => "btime",
+            DateSource::Mtime => "mtime",
+        }
+    }
+}
+
+fn exif_string(exif: &Exif, tag: Tag) -> Option<String> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(ref vec) if !vec.is_empty() => {
+            let s = String::from_utf8_lossy(&vec[0]);
+            let s = s.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            (!s.is_empty()).then(|| s.to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Name of the camera that took the picture, from the EXIF Make and Model tags, made suitable for
+/// use as a directory name.
+fn exif_camera(exif: &Exif) -> Option<String> {
+    let make = exif_string(exif, Tag::Make);
+    let model = exif_string(exif, Tag::Model);
+    let name = match (make, model) {
+        // Models usually repeat the make already ("Canon" / "Canon EOS 80D").
+        (Some(make), Some(model)) => {
+            let first_word = make.split_whitespace().next().unwrap_or_default();
+            if model.to_lowercase().contains(&first_word.to_lowercase()) {
+                model
+            } else {
+                format!("{make} {model}")
+            }
+        }
+        (Some(name), None) | (None, Some(name)) => name,
+        (None, None) => return None,
+    };
+    let name = sanitize_dir_name(&name);
+    (!name.is_empty()).then_some(name)
+}
+
+/// Whether a file taken by the given camera should be processed, according to --camera,
+/// --exclude-camera, and --camera-unknown.
+fn camera_selected(args: &Args, camera: Option<&str>) -> bool {
+    if args.camera.is_empty() && args.exclude_camera.is_empty() {
+        return true;
+    }
+    let Some(camera) = camera else {
+        return args.camera_unknown == CameraUnknown::Include;
+    };
+    let camera = camera.to_lowercase();
+    let matches = |names: &[String]| names.iter().any(|name| camera.contains(&name.to_lowercase()));
+    (args.camera.is_empty() || matches(&args.camera)) && !matches(&args.exclude_camera)
+}
+
+/// Replace characters that aren't allowed in file names (on any common OS) with spaces, and
+/// collapse runs of whitespace.
+fn sanitize_dir_name(s: &str) -> String {
+    let replaced = s.replace(|c: char| c.is_control() || r#"/\:*?"<>|"#.contains(c), " ");
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.trim_end_matches('.').nfc().collect()
+}
 
-    Ok(dt)
+/// If a date from metadata looks wrong, say why.
+fn suspect_date(dt: &DateTime, floor: u16) -> Option<&'static str> {
+    if dt.month == 1 && dt.day == 1 && dt.hour == 0 && dt.minute == 0 && dt.second == 0 {
+        return Some("looks like a camera's reset default");
+    }
+    if dt.year < floor {
+        return Some("is too old");
+    }
+    // Allow for the date being a wall-clock time in any zone ahead of UTC.
+    let latest = chrono::Utc::now().naive_utc() + chrono::Duration::hours(14);
+    let naive = chrono::NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())
+        .and_then(|d| d.and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into()));
+    if naive.is_some_and(|naive| naive > latest) {
+        return Some("is in the future");
+    }
+    None
 }
 
-fn mtime_datetime(file: &File) -> DateTime {
+fn mtime_datetime(file: &File, tz: Option<Tz>) -> DateTime {
     let meta = file.metadata().expect("should be able to read metadata from open file");
-    let chr: chrono::DateTime<chrono::Local> = meta.modified().unwrap().into();
+    utc_wall_clock(&meta.modified().unwrap().into(), tz)
+}
+
+/// Convert a point in time to wall-clock time in the given zone, or the system's.
+fn utc_wall_clock(utc: &chrono::DateTime<chrono::Utc>, tz: Option<Tz>) -> DateTime {
+    match tz {
+        Some(tz) => wall_clock(&localtime::to_local(&tz, utc)),
+        None => wall_clock(&localtime::to_local(&chrono::Local, utc)),
+    }
+}
+
+/// The creation time recorded in a video file.
+fn video_utc(path: &Path) -> anyhow::Result<Option<chrono::DateTime<chrono::Utc>>> {
+    let file = BufReader::new(File::open(path).with_context(|| format!("failed to open {path:?}"))?);
+    let is_matroska = path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("mkv") || e.eq_ignore_ascii_case("webm"));
+    if is_matroska {
+        ebml::date_utc(file).with_context(|| format!("failed to read the segment info of {path:?}"))
+    } else {
+        bmff::creation_time(file).with_context(|| format!("failed to read the movie header of {path:?}"))
+    }
+}
+
+/// A file whose date is to be decided, with what's been read from it already.
+struct Undated<'a> {
+    path: &'a Path,
+    file: &'a File,
+    meta: &'a std::fs::Metadata,
+    /// The extension, in lowercase.
+    ext: Option<&'a str>,
+    exif: Option<&'a Exif>,
+    /// The date in a Camera Uploads style name that's being kept, which wins over everything.
+    kept: Option<DateTime>,
+    /// Metadata that is present but can't be parsed, as opposed to simply missing.
+    bad_metadata: Option<anyhow::Error>,
+}
+
+/// Decides the dates files are named by, from their metadata, name, or modification time,
+/// according to the options.
+struct Dater<'a> {
+    args: &'a Args,
+    exiftool: Option<exiftool::Exiftool>,
+    tz_finder: Option<tzf_rs::DefaultFinder>,
+    chapters: clips::Chapters,
+    /// When the first chapter of each GoPro video was recorded, kept for the chapters after it,
+    /// since with --move it's gone by the time they come up.
+    first_chapter_times: HashMap<PathBuf, chrono::DateTime<chrono::Utc>>,
+    /// Files with dates that look wrong (see --suspect-dates).
+    suspect: Vec<PathBuf>,
+}
+
+impl<'a> Dater<'a> {
+    fn new(args: &'a Args, exiftool: Option<exiftool::Exiftool>) -> Self {
+        Dater {
+            args,
+            exiftool,
+            tz_finder: args.tz_from_gps.then(tzf_rs::DefaultFinder::new),
+            chapters: clips::Chapters::default(),
+            first_chapter_times: HashMap::new(),
+            suspect: vec![],
+        }
+    }
+
+    /// The file's date, where it came from, and which chapter of a GoPro video the file is (if
+    /// its name should say so). Problems with the metadata are reported and fallen back from;
+    /// the error is for files to skip because of --strict.
+    fn date(&mut self, stats: &mut Stats, f: Undated) -> anyhow::Result<(DateTime, DateSource, Option<clips::Chapter>)> {
+        let path = f.path;
+        let mut bad_metadata = f.bad_metadata;
+        if let Some(dt) = f.kept {
+            return match bad_metadata {
+                Some(e) if self.args.strict.is_some() => Err(e),
+                _ => Ok((dt, DateSource::Filename, None)),
+            };
+        }
+        let stem = path.file_stem().and_then(OsStr::to_str);
+
+        let maybe_datetime = f.exif.and_then(|exif| match exif_datetime(exif) {
+            Ok(dt) => dt,
+            Err(e) => {
+                eprintln!("{path:?}: Couldn't get EXIF DateTime: {e:?}");
+                bad_metadata = Some(e);
+                None
+            }
+        });
+
+        // The time zone this file was taken in, if we know better than --timezone.
+        let gps_zone = self.tz_finder.as_ref()
+            .and_then(|finder| {
+                let (lat, lon) = gps::coordinates(f.exif?)?;
+                let name = finder.get_tz_name(lon, lat);
+                match name.parse::<Tz>() {
+                    Ok(tz) => Some(tz),
+                    Err(e) => {
+                        eprintln!("{path:?}: unknown time zone {name:?} at {lat},{lon}: {e}");
+                        None
+                    }
+                }
+            });
+        if let Some(tz) = gps_zone {
+            if self.args.verbose {
+                eprintln!("{path:?}: using time zone {tz} from GPS coordinates");
+            }
+        }
+        let zone = gps_zone.or(self.args.timezone);
+
+        let maybe_datetime = match (gps_zone, f.exif.and_then(gps::utc_datetime)) {
+            (Some(tz), Some(utc)) => Some(wall_clock(&localtime::to_local(&tz, &utc))),
+            _ => maybe_datetime,
+        };
+
+        let mut metadata_source = DateSource::Exif;
+
+        // Google Takeout's JSON file knows better than the file's own metadata, which is often
+        // wrong or missing in Takeout exports.
+        let takeout_json = takeout::sidecar(path);
+        let maybe_datetime = match takeout_json {
+            Some(json) => match takeout::photo_taken(&json) {
+                Ok(Some(utc)) => {
+                    metadata_source = DateSource::TakeoutJson;
+                    Some(utc_wall_clock(&utc, zone))
+                }
+                Ok(None) => maybe_datetime,
+                Err(e) => {
+                    eprintln!("{path:?}: {e:#}");
+                    maybe_datetime
+                }
+            },
+            None => maybe_datetime,
+        };
+
+        // Videos have no EXIF data, but their container says when they were recorded. All the
+        // chapters of a GoPro video are named after the first one, so they stay together.
+        let mut chapter = None;
+        let is_video = matches!(f.ext,
+            Some("mp4") | Some("mov") | Some("m4v") | Some("3gp") | Some("mkv") | Some("webm"));
+        let maybe_datetime = match maybe_datetime {
+            None if is_video => {
+                chapter = self.chapters.get(path);
+                let video = chapter.as_ref().map_or(path, |c| c.first.as_path());
+                let utc = match self.first_chapter_times.get(video) {
+                    Some(&utc) => Ok(Some(utc)),
+                    None => stats.time(Stage::Metadata, || video_utc(video)),
+                };
+                match utc {
+                    Ok(Some(utc)) => {
+                        if let Some(c) = &chapter {
+                            self.first_chapter_times.insert(c.first.clone(), utc);
+                        }
+                        metadata_source = DateSource::Video;
+                        Some(utc_wall_clock(&utc, zone))
+                    }
+                    Ok(None) => {
+                        chapter = None;
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("{path:?}: Couldn't get video creation time: {e:#}");
+                        chapter = None;
+                        bad_metadata = Some(e);
+                        None
+                    }
+                }
+            }
+            other => other,
+        };
+
+        let maybe_datetime = match (maybe_datetime, &self.exiftool) {
+            (None, Some(tool)) => match stats.time(Stage::Metadata, || tool.datetime(path)) {
+                Ok(Some(dt)) => {
+                    metadata_source = DateSource::Exiftool;
+                    Some(dt)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!("{path:?}: exiftool failed: {e:#}");
+                    None
+                }
+            },
+            (dt, _) => dt,
+        };
+
+        let maybe_datetime = match maybe_datetime {
+            Some(dt) if self.args.suspect_dates != SuspectDates::Keep => match suspect_date(&dt, self.args.date_floor) {
+                Some(reason) => {
+                    self.suspect.push(path.to_owned());
+                    if self.args.suspect_dates == SuspectDates::Fallback {
+                        eprintln!("{path:?}: {} date {dt} {reason}, ignoring it", metadata_source.as_str());
+                        chapter = None;
+                        None
+                    } else {
+                        eprintln!("{path:?}: {} date {dt} {reason}", metadata_source.as_str());
+                        Some(dt)
+                    }
+                }
+                None => Some(dt),
+            },
+            other => other,
+        };
+
+        if let Some(strict) = self.args.strict {
+            let error = match bad_metadata {
+                Some(e) => Some(e),
+                None if strict == Strict::All && maybe_datetime.is_none() => Some(anyhow!("no date found in metadata")),
+                None => None,
+            };
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+
+        let (maybe_datetime, metadata_source) = match self.args.date_strategy {
+            DateStrategy::Earliest => {
+                let mut candidates = vec![];
+                if let Some(dt) = maybe_datetime {
+                    candidates.push((metadata_source.as_str(), metadata_source, dt));
+                }
+                if let Some(exif) = f.exif {
+                    for (tag, name) in [(Tag::DateTimeDigitized, "exif DateTimeDigitized"), (Tag::DateTime, "exif DateTime")] {
+                        if let Ok(Some(dt)) = exif_tag_datetime(exif, tag) {
+                            candidates.push((name, DateSource::Exif, dt));
+                        }
+                    }
+                }
+                if let Some(dt) = stem.and_then(filename::filename_datetime) {
+                    candidates.push(("filename", DateSource::Filename, dt));
+                }
+                if let Ok(btime) = f.meta.created() {
+                    candidates.push(("btime", DateSource::Btime, utc_wall_clock(&btime.into(), zone)));
+                }
+                candidates.push(("mtime", DateSource::Mtime, mtime_datetime(f.file, zone)));
+
+                if self.args.suspect_dates != SuspectDates::Keep {
+                    candidates.retain(|(name, _, dt)| match suspect_date(dt, self.args.date_floor) {
+                        Some(reason) => {
+                            if self.args.verbose {
+                                eprintln!("{path:?}: {name} date {dt} {reason}, not considering it");
+                            }
+                            false
+                        }
+                        None => true,
+                    });
+                }
+                match candidates.iter().min_by_key(|(_, _, dt)| date_key(dt)) {
+                    Some(&(name, source, dt)) => {
+                        if self.args.verbose {
+                            let others = candidates.iter()
+                                .map(|(name, _, dt)| format!("{name} {dt}"))
+                                .collect::<Vec<_>>();
+                            eprintln!("{path:?}: using {name} date {dt}, the earliest of: {}", others.join(", "));
+                        }
+                        if source != DateSource::Video {
+                            chapter = None;
+                        }
+                        (Some(dt), source)
+                    }
+                    None => (maybe_datetime, metadata_source),
+                }
+            }
+            _ => (maybe_datetime, metadata_source),
+        };
+
+        let (dt, source) = maybe_datetime.map(|dt| (dt, metadata_source))
+            .or_else(|| stem.and_then(filename::filename_datetime).map(|dt| (dt, DateSource::Filename)))
+            .unwrap_or_else(|| (mtime_datetime(f.file, zone), DateSource::Mtime));
+        Ok((dt, source, chapter))
+    }
+}
+
+/// Convert a date and time in some time zone to the local wall-clock time used for naming.
+fn wall_clock(chr: &(impl Datelike + Timelike)) -> DateTime {
     macro_rules! cast {
         ($n:expr) => {
             $n.try_into().unwrap_or_else(|e| panic!("{} ({}): {}", stringify!($n), $n, e))
@@ -67,83 +885,1365 @@ fn mtime_datetime(file: &File) -> DateTime {
     }
 }
 
-fn main() -> std::io::Result<()> {
-    let args = Args::parse();
-    println!("{args:#?}");
+/// Whether `dst` looks like a copy of the source file, going only by the attributes chosen with
+/// --skip-same.
+fn looks_same(mode: SkipSame, src: &std::fs::Metadata, dst: &Path) -> bool {
+    if mode == SkipSame::Name {
+        return true;
+    }
+    let Ok(dst) = std::fs::metadata(dst) else {
+        return false;
+    };
+    if dst.len() != src.len() {
+        return false;
+    }
+    match (mode, src.modified(), dst.modified()) {
+        (SkipSame::NameSizeMtime, Ok(src), Ok(dst)) => dst >= src,
+        (SkipSame::NameSizeMtime, _, _) => false,
+        _ => true,
+    }
+}
 
-    for entry in WalkDir::new(&args.src) {
-        let entry = entry?;
-        if entry.file_type().is_dir() {
-            continue;
-        }
-        let path = entry.path();
-        let file = match File::open(path) {
-            Ok(f) => f,
+/// Whether `path` has the same contents as `other`. The hash of `path` is computed at most once,
+/// and kept in `path_hash` for comparisons against other files.
+fn is_duplicate(
+    path: &Path,
+    path_hash: &mut Option<String>,
+    other: &Path,
+    algorithm: HashAlgorithm,
+    stats: &mut Stats,
+    index: Option<&mut HashIndex>,
+) -> std::io::Result<bool> {
+    if std::fs::metadata(path)?.len() != std::fs::metadata(other)?.len() {
+        return Ok(false);
+    }
+    let path_hash = source_hash(path, path_hash, algorithm, stats)?;
+    let other_hash = match index {
+        Some(index) => stats.time(Stage::Hash, || index.hash(other))?,
+        None => stats.time(Stage::Hash, || hash::hash_file(other, algorithm))?,
+    };
+    Ok(path_hash == other_hash)
+}
+
+/// The hash of `path`, computed at most once and kept in `path_hash`.
+fn source_hash(
+    path: &Path,
+    path_hash: &mut Option<String>,
+    algorithm: HashAlgorithm,
+    stats: &mut Stats,
+) -> std::io::Result<String> {
+    if let Some(hash) = path_hash {
+        return Ok(hash.clone());
+    }
+    let hash = stats.time(Stage::Hash, || hash::hash_file(path, algorithm))?;
+    *path_hash = Some(hash.clone());
+    Ok(hash)
+}
+
+/// Files in the destination that aren't in `expected`, leaving out the ones this program keeps
+/// there for itself, for --verify-extra.
+fn unexpected_files(args: &Args, expected: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    jwalk::WalkDir::new(&args.dst)
+        .sort(true)
+        .skip_hidden(false)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(e) if e.file_type().is_dir() => None,
+            Ok(e) => Some(e.path()),
             Err(e) => {
-                eprintln!("failed to open file {path:?}: {e:?}");
-                continue;
+                eprintln!("error reading the destination: {e}");
+                None
             }
-        };
-
-        let maybe_datetime = match path.extension().and_then(OsStr::to_str).map(str::to_ascii_lowercase).as_deref() {
-            Some("jpg") | Some("jpeg")
-                | Some("tif") | Some("tiff")
-                | Some("cr2") // basically tif
-                | Some("heif") | Some("heic") | Some("avif")
-                | Some("png")
-                | Some("webp") => match exif_datetime(&file)
-            {
-                Ok(dt) => Some(dt),
-                Err(e) => {
-                    eprintln!("{path:?}: Couldn't get EXIF DateTime: {e:?}");
-                    None
-                }
-            },
-            _ => None,
-        };
+        })
+        .filter(|path| !expected.contains(path)
+            && !origin::is_sidecar(path)
+            && !lock::is_lock_file(path)
+            && args.hash_index.as_deref() != Some(path.as_path())
+            && !args.duplicates_to.as_ref().is_some_and(|dir| path.starts_with(dir)))
+        .collect()
+}
 
-        let datetime = maybe_datetime.unwrap_or_else(|| mtime_datetime(&file));
+/// Write a --print0 record for a file.
+fn print0(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+    out.write_all(&rawpath::to_bytes(src))?;
+    out.write_all(b"\0")?;
+    out.write_all(&rawpath::to_bytes(dst))?;
+    out.write_all(b"\0")?;
+    out.flush()
+}
 
-        let filename = |n: usize| {
-            let mut s = format!("{:04}-{:02}-{:02} {:02}.{:02}.{:02}",
-                datetime.year,
-                datetime.month,
-                datetime.day,
-                datetime.hour,
-                datetime.minute,
-                datetime.second);
-            if n > 0 {
-                s += &n.to_string();
-            }
-            if let Some(ext) = path.extension().and_then(OsStr::to_str) {
-                s.push('.');
-                s += ext;
-            }
-            s
-        };
+/// The path with its `.` components left out, so that it's spelled the same way as the paths
+/// the ignore patterns are matched against (which never start with `./`).
+fn without_cur_dir(path: &Path) -> PathBuf {
+    let path = path.components().filter(|c| c != &std::path::Component::CurDir).collect::<PathBuf>();
+    if path.as_os_str().is_empty() { PathBuf::from(".") } else { path }
+}
 
-        let mut new_path = args.dst
-            .join(datetime.year.to_string());
+/// Where a file is written before it's put in place under its real name, so that a copy cut
+/// short never leaves a partial file that looks like a finished one. The name is short and
+/// fixed, since the real one may already be as long as names can be; only one file is written
+/// at a time.
+fn temp_path(dst: &Path) -> PathBuf {
+    dst.with_file_name(format!(".cu_backfill-{}.tmp", std::process::id()))
+}
 
-        if !new_path.exists() && !args.dry_run {
-            std::fs::create_dir_all(&new_path).unwrap();
+/// Give a finished temporary file its real name, which must not be taken.
+fn persist(tmp: &Path, dst: &Path) -> std::io::Result<()> {
+    match std::fs::hard_link(tmp, dst) {
+        Ok(()) => std::fs::remove_file(tmp),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(e),
+        // The filesystem may not have hard links at all (FAT and exFAT don't).
+        Err(_) if dst.symlink_metadata().is_ok() => {
+            Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination already exists"))
         }
+        Err(_) => std::fs::rename(tmp, dst),
+    }
+}
 
-        new_path.push(filename(0));
+/// Run `write` on the temporary file for `dst`, then put it in place, cleaning it up if either
+/// step fails.
+fn write_via_temp<T>(dst: &Path, write: impl FnOnce(&Path) -> std::io::Result<T>) -> std::io::Result<T> {
+    let tmp = temp_path(dst);
+    let result = write(&tmp).and_then(|value| persist(&tmp, dst).map(|()| value));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
 
-        let mut n = 1;
-        while new_path.exists() {
-            new_path.set_file_name(filename(n));
-            n += 1;
+/// Copy a file, checking that it didn't change size while being copied (e.g. because something
+/// is still writing it). If it did, the copy is tried once more before giving up.
+fn copy_file(src: &Path, dst: &Path) -> std::io::Result<u64> {
+    write_via_temp(dst, |tmp| {
+        for _ in 0..2 {
+            let before = std::fs::metadata(src)?.len();
+            let len = std::fs::copy(src, tmp)?;
+            let after = std::fs::metadata(src)?.len();
+            if before == len && len == after {
+                return Ok(len);
+            }
+            eprintln!("{src:?} changed size while being copied ({before} -> {after} bytes)");
         }
+        Err(std::io::Error::other("file kept changing while being copied"))
+    })
+}
 
-        if args.dry_run {
-            println!("{path:?} -> {new_path:?}");
-        } else if let Err(e) = std::fs::copy(path, &new_path) {
-            eprintln!("failed to copy {path:?} to {new_path:?}: {e}");
-            continue;
+/// Write a file that must not already exist.
+fn write_new_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    write_via_temp(path, |tmp| std::fs::write(tmp, data))
+}
+
+/// Move a file, falling back to copying and deleting it if it can't simply be renamed (e.g.
+/// because it's on another filesystem). Returns the number of bytes moved.
+fn move_file(src: &Path, dst: &Path) -> std::io::Result<u64> {
+    let len = std::fs::metadata(src)?.len();
+    // A rename would replace a file that got to `dst` first; a hard link fails instead.
+    match std::fs::hard_link(src, dst) {
+        Ok(()) => {
+            std::fs::remove_file(src)?;
+            return Ok(len);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(e),
+        Err(_) if dst.symlink_metadata().is_ok() => {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination already exists"));
+        }
+        // No hard links on this filesystem, or `dst` is on another one.
+        Err(_) => {
+            if std::fs::rename(src, dst).is_ok() {
+                return Ok(len);
+            }
         }
     }
+    let len = copy_file(src, dst)?;
+    std::fs::remove_file(src)?;
+    Ok(len)
+}
 
-    Ok(())
+/// Exit code for a run that was stopped early by SIGINT or SIGTERM.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Exit code for a run that was stopped early because the destination is running out of space.
+const EXIT_LOW_SPACE: u8 = 3;
+
+/// Exit code for a run that was stopped early because of --max-errors.
+const EXIT_TOO_MANY_ERRORS: u8 = 4;
+
+fn main() -> std::io::Result<ExitCode> {
+    if std::env::args_os().nth(1).as_deref() == Some(OsStr::new("where")) {
+        let args = origin::WhereArgs::parse_from(std::env::args_os().skip(1));
+        if let Err(e) = origin::where_main(args) {
+            eprintln!("{e:?}");
+            return Ok(ExitCode::FAILURE);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut args = Args::parse();
+    args.src = without_cur_dir(&args.src);
+    if args.verbose {
+        eprintln!("{args:#?}");
+    }
+
+    let dry_run = args.dry_run || args.emit_script.is_some() || args.diff || args.verify_only;
+
+    let mut exclude_dirs = args.exclude_dir.iter().map(String::as_str).collect::<Vec<_>>();
+    if !args.no_default_excludes {
+        exclude_dirs.extend_from_slice(DEFAULT_EXCLUDE_DIRS);
+    }
+    let ignore = match ignorefile::load(&args.src, args.ignore_file.as_deref()) {
+        Ok(ignore) => Arc::new(ignore),
+        Err(e) => {
+            eprintln!("failed to read ignore patterns: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    if let Some(why) = &args.why {
+        let path = args.src.join(why);
+        let excluded = path.strip_prefix(&args.src).ok()
+            .and_then(|rel| rel.iter().find(|&c| exclude_dirs.iter().any(|&name| c == name)));
+        match (excluded, ignorefile::matched_with_parents(&ignore, &path, path.is_dir())) {
+            (Some(name), _) => println!("{path:?} is skipped: it's in an excluded directory {name:?}"),
+            (None, ignore::Match::Ignore(glob)) => {
+                println!("{path:?} is skipped because of pattern {}", ignorefile::describe(glob));
+            }
+            (None, ignore::Match::Whitelist(glob)) => {
+                println!("{path:?} is not skipped: pattern {} brings it back", ignorefile::describe(glob));
+            }
+            (None, ignore::Match::None) => println!("{path:?} is not skipped: no pattern matches it"),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let problems = preflight::check(&args.src, &args.dst, dry_run);
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let mut script = match &args.emit_script {
+        Some(path) => match Script::create(path, args.emit_script_format) {
+            Ok(script) => Some(script),
+            Err(e) => {
+                eprintln!("failed to create script {path:?}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
+    let mut taken = Taken::default();
+
+    if cfg!(not(unix)) && args.chown.is_some() {
+        eprintln!("--chown has no effect on this platform");
+    }
+
+    let exiftool = match &args.exiftool {
+        Some(program) => {
+            let timeout = std::time::Duration::from_secs(args.exiftool_timeout);
+            match exiftool::Exiftool::new(program.clone(), timeout) {
+                Ok(tool) => Some(tool),
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut hash_index = match &args.hash_index {
+        Some(index_path) => match HashIndex::load(index_path, &args.dst, args.hash) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                eprintln!("{e:?}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
+
+    let fat = match fat::detect(&args.dst) {
+        Some(kind) => {
+            if !args.fat_safe {
+                eprintln!("destination is {kind:?}; turning on --fat-safe");
+            }
+            Some(kind)
+        }
+        // Without knowing better, assume the stricter limits.
+        None if args.fat_safe => Some(Fat::Fat32),
+        None => None,
+    };
+
+    let _lock = if dry_run || args.no_lock {
+        None
+    } else {
+        match lock::acquire(&args.dst) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("{e:?}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    };
+
+    // The first SIGINT or SIGTERM lets the file in progress finish and then stops the run; a
+    // second one exits immediately.
+    let stop = Arc::new(AtomicBool::new(false));
+    for sig in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(sig, EXIT_INTERRUPTED.into(), Arc::clone(&stop))?;
+        signal_hook::flag::register(sig, Arc::clone(&stop))?;
+    }
+    let mut interrupted = false;
+
+    // Start watching before the initial pass, so files that show up during it aren't missed.
+    let watcher = if args.watch {
+        let debounce = std::time::Duration::from_secs(args.watch_debounce);
+        match watch::Watcher::new(&args.src, debounce, Arc::clone(&stop)) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                eprintln!("failed to watch {:?}: {e}", args.src);
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    } else {
+        None
+    };
+    let mut free_space = args.min_free.map(|min| FreeSpace::new(args.dst.clone(), min));
+    let mut low_space = false;
+    let mut too_many_errors = false;
+
+    let mut stats = Stats::new(args.stats);
+    let mut copied = 0u64;
+    let mut copied_bytes = 0u64;
+    let mut failed = vec![];
+    let mut walk_errors = 0usize;
+    let mut duplicates = 0u64;
+    let mut quick_skipped = 0u64;
+    let mut empty = vec![];
+    let mut too_new = vec![];
+    let mut too_big = vec![];
+    let mut other_camera = 0u64;
+    // Source files moved away, for --prune-empty-dirs.
+    let mut moved = HashSet::new();
+    let min_mtime = args.min_age
+        .map(|secs| std::time::SystemTime::now() - std::time::Duration::from_secs(secs));
+    let dedupe = args.dedupe || args.duplicates_to.is_some() || args.diff || args.verify_only;
+    let mut diff = if args.verify_only {
+        Some(Diff::audit())
+    } else {
+        args.diff.then(Diff::default)
+    };
+    // Destination files accounted for by a source file, for --verify-extra.
+    let mut expected = HashSet::new();
+    let mut histogram = args.histogram.then(Histogram::default);
+    let mut duplicate_report = args.report_duplicates.as_ref().map(|_| DuplicateReport::default());
+
+    let pruned_dirs = Arc::new(AtomicU64::new(0));
+    let ignored = Arc::new(AtomicU64::new(0));
+
+    // Directories are read in parallel, but entries still come out in sorted order.
+    let walker = {
+        let exclude_dirs = exclude_dirs.iter().map(|&name| name.to_owned()).collect::<Vec<_>>();
+        let pruned_dirs = Arc::clone(&pruned_dirs);
+        let ignore = Arc::clone(&ignore);
+        let ignored = Arc::clone(&ignored);
+        let verbose = args.verbose;
+        let show_ignored = args.verbose || dry_run;
+        jwalk::WalkDir::new(&args.src)
+            .sort(true)
+            .skip_hidden(false)
+            .process_read_dir(move |_depth, _path, _state, children| {
+                children.retain(|entry| {
+                    let Ok(e) = entry else {
+                        return true;
+                    };
+                    let excluded = e.file_type().is_dir()
+                        && exclude_dirs.iter().any(|name| e.file_name() == name.as_str());
+                    if excluded {
+                        if verbose {
+                            eprintln!("skipping excluded directory {:?}", e.path());
+                        }
+                        pruned_dirs.fetch_add(1, Ordering::Relaxed);
+                        return false;
+                    }
+                    // Parent directories have been checked already, so only the entry's own name
+                    // needs to be matched.
+                    if let Some(glob) = ignorefile::matched(&ignore, &e.path(), e.file_type().is_dir()) {
+                        if show_ignored {
+                            eprintln!("ignoring {:?} because of pattern {}", e.path(), ignorefile::describe(glob));
+                        }
+                        ignored.fetch_add(1, Ordering::Relaxed);
+                        return false;
+                    }
+                    true
+                });
+            })
+    };
+
+    // The walk prunes excluded and ignored directories; paths that come from anywhere else have to
+    // be filtered the same way.
+    let in_excluded_dir = |path: &Path| {
+        path.strip_prefix(&args.src).ok()
+            .and_then(Path::parent)
+            .is_some_and(|dir| dir.iter().any(|c| exclude_dirs.iter().any(|&name| c == name)))
+            || ignorefile::matched_with_parents(&ignore, path, false).is_ignore()
+    };
+
+    let mut total = None;
+    let mut input: Box<dyn Iterator<Item = std::io::Result<PathBuf>> + '_> = match &args.files_from {
+        Some(list) => match filelist::read(list, args.from0, &args.src) {
+            Ok(mut paths) => {
+                paths.retain(|path| {
+                    if in_excluded_dir(path) {
+                        if args.verbose || dry_run {
+                            eprintln!("skipping {path:?}: it's excluded or ignored");
+                        }
+                        return false;
+                    }
+                    true
+                });
+                total = Some(paths.len() as u64);
+                Box::new(paths.into_iter().map(Ok))
+            }
+            Err(e) => {
+                eprintln!("failed to read list of files from {list:?}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => Box::new(walker.into_iter().filter_map(|entry| match entry {
+            Ok(entry) if entry.file_type().is_dir() => None,
+            Ok(entry) => Some(Ok(entry.path())),
+            Err(e) => Some(Err(e.into())),
+        })),
+    };
+    if let Some(watcher) = watcher {
+        input = Box::new(input.chain(watcher.filter(move |p| !p.as_ref().is_ok_and(|p| in_excluded_dir(p)))));
+    }
+
+    let status = Status::start(total)?;
+    let mut done = 0u64;
+    let run_started = chrono::Utc::now();
+    let mut mark_processed = args.mark_processed;
+    let mut marked = 0u64;
+    // The watcher starts before the walk, so files written while the walk runs can come from
+    // both; the size and mtime each file had when it was processed tell repeats from changes.
+    let mut seen = HashMap::<PathBuf, (u64, Option<std::time::SystemTime>)>::new();
+    let mut takeout_jsons = takeout::Sidecars::default();
+    let mut moved_sidecars = HashSet::new();
+    let mut dater = Dater::new(&args, exiftool);
+
+    while let Some(entry) = stats.time(Stage::Walk, || input.next()) {
+        status.wait_while_paused(&stop);
+        if stop.load(Ordering::Relaxed) {
+            interrupted = true;
+            break;
+        }
+        if args.max_errors > 0 && failed.len() + walk_errors >= args.max_errors {
+            eprintln!("{} errors; stopping because of --max-errors", failed.len() + walk_errors);
+            too_many_errors = true;
+            break;
+        }
+        let path_buf = match entry {
+            Ok(path) => path,
+            Err(e) if args.halt_on_walk_error => return Err(e),
+            Err(e) => {
+                eprintln!("error reading the source tree: {e}");
+                walk_errors += 1;
+                continue;
+            }
+        };
+        status.update(|progress| {
+            progress.done = done;
+            progress.bytes = copied_bytes;
+            progress.errors = (failed.len() + walk_errors) as u64;
+            progress.current = Some(path_buf.clone());
+        });
+        done += 1;
+        let path = path_buf.as_path();
+        // Sidecars moved along with their video are still in the walk, which listed them before.
+        if moved_sidecars.remove(path) {
+            continue;
+        }
+        if origin::is_sidecar(path) || lock::is_lock_file(path) || clips::is_sidecar(path)
+            || ignorefile::is_ignore_file(&args.src, path) || takeout_jsons.contains(path)
+        {
+            continue;
+        }
+        if args.clear_markers && dry_run {
+            if marker::is_marked(path) {
+                eprintln!("would remove the mark from {path:?}");
+                marked += 1;
+            }
+            continue;
+        }
+        if args.clear_markers {
+            match marker::clear(path) {
+                Ok(true) => marked += 1,
+                Ok(false) => (),
+                Err(e) => {
+                    eprintln!("failed to remove the mark from {path:?}: {e}");
+                    failed.push(path.to_owned());
+                }
+            }
+            continue;
+        }
+        if args.mark_processed && !args.ignore_markers && marker::is_marked(path) {
+            if args.verbose {
+                eprintln!("{path:?} is marked as processed, skipping");
+            }
+            marked += 1;
+            continue;
+        }
+        let meta = match std::fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                eprintln!("failed to read metadata of {path:?}: {e}");
+                failed.push(path.to_owned());
+                continue;
+            }
+        };
+        if meta.is_dir() {
+            eprintln!("{path:?} is a directory, skipping");
+            continue;
+        }
+        if args.watch {
+            let state = (meta.len(), meta.modified().ok());
+            if seen.insert(path.to_owned(), state) == Some(state) {
+                if args.verbose {
+                    eprintln!("{path:?} hasn't changed since it was processed, skipping");
+                }
+                continue;
+            }
+        }
+        if !args.keep_empty && meta.len() == 0 {
+            empty.push(path.to_owned());
+            continue;
+        }
+        if let Some(min_mtime) = min_mtime {
+            if meta.modified().is_ok_and(|t| t > min_mtime) {
+                too_new.push(path.to_owned());
+                continue;
+            }
+        }
+        if fat == Some(Fat::Fat32) && meta.len() > fat::MAX_FAT32_FILE_SIZE {
+            eprintln!("{path:?} is too big for FAT32 ({} bytes), skipping", meta.len());
+            too_big.push(path.to_owned());
+            continue;
+        }
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("failed to open file {path:?}: {e:?}");
+                failed.push(path.to_owned());
+                continue;
+            }
+        };
+
+        // A file already named in Camera Uploads style: keep the name verbatim if asked to.
+        let stem = path.file_stem().and_then(OsStr::to_str);
+        let kept_name = stem
+            .filter(|_| args.keep_cu_names)
+            .and_then(|stem| match filename::camera_uploads_datetime(stem) {
+                Some((dt, true)) => Some((dt, stem.to_owned())),
+                _ => None,
+            });
+
+        // Metadata that is present but can't be parsed, as opposed to simply missing.
+        let mut bad_metadata = None;
+
+        let filter_camera = !args.camera.is_empty() || !args.exclude_camera.is_empty();
+        let needs_exif = kept_name.is_none() || args.by_camera || filter_camera || args.strip_gps;
+        let ext = path.extension().and_then(OsStr::to_str).map(str::to_ascii_lowercase);
+        let maybe_exif = match ext.as_deref() {
+            _ if !needs_exif => None,
+            Some("jpg") | Some("jpeg")
+                | Some("mpo") // JPEGs one after another; the first has the EXIF data
+                | Some("tif") | Some("tiff")
+                | Some("cr2") // basically tif
+                | Some("heif") | Some("heic") | Some("avif")
+                | Some("png")
+                | Some("webp")
+                | Some("jxl") => match stats.time(Stage::Metadata, || read_exif(&file, ext.as_deref().unwrap_or_default()))
+            {
+                Ok(exif) => Some(exif),
+                Err(e) => {
+                    // Only files that fail the normal parse pay for a second, slower look.
+                    let resynced = match ext.as_deref() {
+                        Some("jpg") | Some("jpeg") | Some("mpo") => stats.time(Stage::Metadata, || jpeg::resync_exif(&file)),
+                        Some("heif") | Some("heic") | Some("avif") => stats.time(Stage::Metadata, || heif_exif(&file)),
+                        _ => Ok(None),
+                    };
+                    let resynced = resynced.unwrap_or_else(|e| {
+                        eprintln!("{path:?}: failed to search for EXIF data: {e}");
+                        None
+                    });
+                    if resynced.is_some() {
+                        if args.verbose {
+                            eprintln!("{path:?}: found EXIF data by searching the file ({e:#})");
+                        }
+                    } else if is_missing_exif(&e) {
+                        // Plenty of files just don't have any, JPEG XL ones especially.
+                        if args.verbose {
+                            eprintln!("{path:?}: no EXIF data");
+                        }
+                    } else {
+                        eprintln!("{path:?}: Couldn't read EXIF: {e:?}");
+                        bad_metadata = Some(e);
+                    }
+                    resynced
+                }
+            },
+            _ => None,
+        };
+
+        let camera = maybe_exif.as_ref().and_then(exif_camera);
+        if !camera_selected(&args, camera.as_deref()) {
+            if args.verbose {
+                eprintln!("{path:?}: skipping file from camera {camera:?}");
+            }
+            other_camera += 1;
+            continue;
+        }
+
+        let exif_missing = needs_exif && maybe_exif.is_none() && bad_metadata.is_none();
+
+        let undated = Undated {
+            path,
+            file: &file,
+            meta: &meta,
+            ext: ext.as_deref(),
+            exif: maybe_exif.as_ref(),
+            kept: kept_name.as_ref().map(|&(dt, _)| dt),
+            bad_metadata,
+        };
+        let (datetime, date_source, chapter) = match dater.date(&mut stats, undated) {
+            Ok(date) => date,
+            Err(e) => {
+                eprintln!("{path:?}: {e:#}; skipping because of --strict");
+                failed.push(path.to_owned());
+                continue;
+            }
+        };
+
+        let (base, original) = match kept_name {
+            Some((_, name)) => (name, String::new()),
+            None => {
+                let mut base = format!("{:04}-{:02}-{:02} {:02}.{:02}.{:02}",
+                    datetime.year,
+                    datetime.month,
+                    datetime.day,
+                    datetime.hour,
+                    datetime.minute,
+                    datetime.second);
+                if let Some(chapter) = &chapter {
+                    base += &format!("-{:02}", chapter.number);
+                }
+                let mut original = String::new();
+                if args.append_original_name {
+                    original = path.file_stem()
+                        .map(|s| filename::sanitize_original_name(&s.to_string_lossy()))
+                        .unwrap_or_default();
+                    if original == base {
+                        original.clear();
+                    }
+                }
+                (base, original)
+            }
+        };
+
+        let truncated = Cell::new(false);
+        let filename = |suffix: &str| {
+            let ext = path.extension().and_then(OsStr::to_str);
+            let (s, shortened) = filename::generated_name(&base, &original, suffix, ext);
+            if shortened && !truncated.replace(true) {
+                eprintln!("{path:?}: name would be longer than {} bytes; shortening the original name in it",
+                    filename::MAX_GENERATED_NAME_BYTES);
+            }
+            if fat.is_some() {
+                fat::sanitize_name(&s)
+            } else {
+                s
+            }
+        };
+
+        let mut new_path = args.dst
+            .join(datetime.year.to_string());
+
+        if args.by_camera {
+            match (&camera, args.unknown_camera) {
+                (Some(camera), _) => new_path.push(camera),
+                (None, UnknownCamera::Folder) => new_path.push("Unknown"),
+                (None, UnknownCamera::Root) => (),
+            }
+        }
+
+        if args.keep_structure {
+            if let Some(dir) = path.strip_prefix(&args.src).ok().and_then(Path::parent) {
+                let names = dir.components().filter_map(|c| match c {
+                    Component::Normal(name) => Some(name),
+                    _ => None,
+                });
+                for c in names.filter(|&c| !exclude_dirs.iter().any(|&name| c == name)) {
+                    match c.to_str() {
+                        Some(c) if fat.is_some() => new_path.push(fat::sanitize_name(&c.nfc().collect::<String>())),
+                        Some(c) => new_path.push(c.nfc().collect::<String>()),
+                        None => new_path.push(c),
+                    }
+                }
+            }
+        }
+
+        // Checked before any directories are made for it.
+        let first_name = filename("");
+        if let Some(problem) = filename::length_problem(&new_path.join(&first_name)) {
+            eprintln!("{path:?}: can't copy it: {problem}");
+            failed.push(path.to_owned());
+            continue;
+        }
+
+        if !new_path.exists() && !dry_run {
+            let created = new_path.ancestors()
+                .take_while(|dir| !dir.exists())
+                .map(Path::to_owned)
+                .collect::<Vec<_>>();
+            if let Err(e) = std::fs::create_dir_all(&new_path) {
+                eprintln!("failed to create directory {new_path:?} for {path:?}: {e}");
+                failed.push(path.to_owned());
+                continue;
+            }
+            if let Some(owner) = args.chown {
+                for dir in &created {
+                    if let Err(e) = owner::apply(owner, dir) {
+                        eprintln!("failed to set the owner of {dir:?}: {e}");
+                    }
+                }
+            }
+        }
+
+        new_path.push(first_name);
+
+        enum Found {
+            /// Looks the same going by --skip-same.
+            Same,
+            /// Has identical contents.
+            Duplicate,
+        }
+        // Whether a taken name already holds this file.
+        let check = |candidate: &Path,
+                     compare: bool,
+                     src_hash: &mut Option<String>,
+                     stats: &mut Stats,
+                     taken: &mut Taken,
+                     hash_index: &mut Option<HashIndex>| {
+            // Files planned by a dry run don't exist yet; compare with the source file that
+            // would be copied there instead.
+            if dry_run && !taken.on_disk(candidate) {
+                let planned = taken.planned_source(candidate)?;
+                if compare {
+                    match is_duplicate(path, src_hash, &planned, args.hash, stats, None) {
+                        Ok(true) => return Some(Found::Duplicate),
+                        Ok(false) => (),
+                        Err(e) => eprintln!("failed to compare {path:?} with {planned:?}: {e}"),
+                    }
+                }
+                return None;
+            }
+            // Names are taken regardless of case, but only a file by exactly this name can be
+            // compared, which on a case-sensitive filesystem there may not be.
+            if (compare || args.skip_same.is_some()) && candidate.symlink_metadata().is_err() {
+                return None;
+            }
+            if let Some(mode) = args.skip_same {
+                if looks_same(mode, &meta, candidate) {
+                    return Some(Found::Same);
+                }
+            }
+            if compare {
+                match is_duplicate(path, src_hash, candidate, args.hash, stats, hash_index.as_mut()) {
+                    Ok(true) => return Some(Found::Duplicate),
+                    Ok(false) => (),
+                    Err(e) => eprintln!("failed to compare {path:?} with {candidate:?}: {e}"),
+                }
+            }
+            None
+        };
+
+        // A taken name gets a counter suffix, or with --suffix hash, the start of the file's hash
+        // (and a counter after that, if even that is taken). Names in the other style are checked
+        // too, though never chosen, so destinations written in both styles still work.
+        let mut src_hash = None;
+        let mut found = None;
+        let mut collided = false;
+        let mut use_hash = args.suffix == Suffix::Hash;
+        let mut hash_suffix = None;
+        let mut hashed = false;
+        // A name with this file's hash in it is most likely this very file, copied by an earlier
+        // run, so it's compared even without --dedupe. With --suffix hash, so is the plain name,
+        // which the first file with this date got.
+        let plain = new_path.clone();
+        let worth_comparing = |candidate: &Path, hash_suffix: &Option<String>, use_hash: bool| {
+            (use_hash && candidate == plain)
+                || hash_suffix.as_ref()
+                    .is_some_and(|hash| candidate.file_name() == Some(OsStr::new(&filename(hash))))
+        };
+        let mut n = 1;
+        'candidates: while taken.contains(&new_path) {
+            collided = true;
+            let compare = dedupe || worth_comparing(&new_path, &hash_suffix, use_hash);
+            if let Some(f) = check(&new_path, compare, &mut src_hash, &mut stats, &mut taken, &mut hash_index) {
+                found = Some((f, new_path.clone()));
+                break;
+            }
+
+            let compare_other_style = dedupe || args.skip_same.is_some();
+            if !hashed && (use_hash || compare_other_style) {
+                hashed = true;
+                match source_hash(path, &mut src_hash, args.hash, &mut stats) {
+                    Ok(hash) => hash_suffix = Some(hash[..8].to_owned()),
+                    Err(e) => {
+                        eprintln!("failed to hash {path:?}, using a counter suffix instead: {e}");
+                        use_hash = false;
+                    }
+                }
+                let others = match (&hash_suffix, use_hash) {
+                    // Counter names already there, up to the first free one.
+                    (Some(_), true) => (1..)
+                        .map(|k| new_path.with_file_name(filename(&k.to_string())))
+                        .take_while(|candidate| taken.contains(candidate))
+                        .collect(),
+                    (Some(hash), false) if compare_other_style => vec![new_path.with_file_name(filename(hash))],
+                    _ => vec![],
+                };
+                for candidate in others {
+                    if !taken.contains(&candidate) {
+                        continue;
+                    }
+                    let compare = dedupe || worth_comparing(&candidate, &hash_suffix, use_hash);
+                    if let Some(f) = check(&candidate, compare, &mut src_hash, &mut stats, &mut taken, &mut hash_index) {
+                        found = Some((f, candidate));
+                        break 'candidates;
+                    }
+                }
+                if let (Some(hash), true) = (&hash_suffix, use_hash) {
+                    new_path.set_file_name(filename(hash));
+                    continue;
+                }
+            }
+
+            let suffix = match (&hash_suffix, use_hash) {
+                (Some(hash), true) => format!("{hash}-{n}"),
+                _ => n.to_string(),
+            };
+            new_path.set_file_name(filename(&suffix));
+            n += 1;
+        }
+        let (duplicate_of, same_as) = match found {
+            Some((Found::Duplicate, existing)) => (Some(existing), None),
+            Some((Found::Same, existing)) => (None, Some(existing)),
+            None => (None, None),
+        };
+
+        if let Some(diff) = &mut diff {
+            let existing = duplicate_of.as_ref().or(same_as.as_ref());
+            let class = match existing {
+                Some(_) => diff::Class::Duplicate,
+                None if collided => diff::Class::Conflict,
+                None => diff::Class::New,
+            };
+            diff.add(class, path, existing.unwrap_or(&new_path));
+            if let (true, Some(existing)) = (args.verify_extra, existing) {
+                expected.insert(existing.clone());
+                for sidecar in clips::sidecars(path) {
+                    expected.insert(existing.with_extension(sidecar.extension().unwrap_or_default()));
+                }
+            }
+        }
+
+        if let Some(existing) = same_as {
+            if args.verbose {
+                eprintln!("{path:?} looks the same as {existing:?}, skipping");
+            }
+            quick_skipped += 1;
+            continue;
+        }
+
+        if let Some(report) = &mut duplicate_report {
+            if src_hash.is_none() {
+                match stats.time(Stage::Hash, || hash::hash_file(path, args.hash)) {
+                    Ok(hash) => src_hash = Some(hash),
+                    Err(e) => eprintln!("failed to hash {path:?}: {e}"),
+                }
+            }
+            if let Some(hash) = &src_hash {
+                report.add_source(hash, path);
+                if let Some(existing) = &duplicate_of {
+                    report.set_destination(hash, existing);
+                }
+            }
+        }
+
+        if let Some(existing) = duplicate_of {
+            duplicates += 1;
+            let Some(dir) = &args.duplicates_to else {
+                if diff.is_none() {
+                    eprintln!("{path:?} is a duplicate of {existing:?}, skipping");
+                }
+                continue;
+            };
+            let quarantine = dir.join(path.strip_prefix(&args.src).unwrap_or(path));
+            if dry_run {
+                if let Some(script) = &mut script {
+                    script.copy(path, &quarantine, true)?;
+                } else {
+                    eprintln!("{path:?} is a duplicate of {existing:?}, would move to {quarantine:?}");
+                }
+                if args.prune_empty_dirs {
+                    moved.insert(path.to_owned());
+                }
+                continue;
+            }
+            drop(file);
+            let result = if quarantine.exists() {
+                Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination already exists"))
+            } else {
+                quarantine.parent().map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|()| move_file(path, &quarantine))
+            };
+            match result {
+                Ok(_) => {
+                    eprintln!("{path:?} is a duplicate of {existing:?}, moved to {quarantine:?}");
+                    if args.prune_empty_dirs {
+                        moved.insert(path.to_owned());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to move duplicate {path:?} to {quarantine:?}: {e}");
+                    failed.push(path.to_owned());
+                }
+            }
+            continue;
+        }
+
+        // Sidecar files of a video, and where they go: next to it, under the same name.
+        let sidecars = if args.skip_video_sidecars { vec![] } else { clips::sidecars(path) };
+        let sidecars = sidecars.into_iter()
+            .map(|src| {
+                let dst = new_path.with_extension(src.extension().unwrap_or_default());
+                (src, dst)
+            })
+            .collect::<Vec<_>>();
+
+        if dry_run {
+            for (src, dst) in std::iter::once((path, &new_path)).chain(sidecars.iter().map(|(s, d)| (s.as_path(), d))) {
+                if let Some(script) = &mut script {
+                    script.copy(src, dst, args.move_files)?;
+                } else if !args.print0 && diff.is_none() {
+                    // The plan is the only thing that goes to stdout.
+                    println!("{src:?} -> {dst:?}");
+                }
+                if args.print0 {
+                    print0(src, dst)?;
+                }
+            }
+            taken.insert(&new_path, path);
+            for (src, dst) in &sidecars {
+                taken.insert(dst, src);
+            }
+            if args.prune_empty_dirs {
+                moved.insert(path.to_owned());
+                moved.extend(sidecars.into_iter().map(|(src, _)| src));
+            }
+            if let Some(histogram) = &mut histogram {
+                histogram.add(datetime.year, datetime.month, meta.len(), date_source == DateSource::Mtime);
+            }
+            copied += 1;
+            continue;
+        }
+
+        if let Some(free_space) = &mut free_space {
+            let len = std::fs::metadata(path).map_or(0, |m| m.len());
+            match free_space.would_exceed(len) {
+                Ok(false) => (),
+                Ok(true) => {
+                    eprintln!("destination has less than {} bytes free; stopped before {path:?}", free_space.min());
+                    low_space = true;
+                    break;
+                }
+                Err(e) => eprintln!("failed to check free space in {:?}: {e}", args.dst),
+            }
+        }
+
+        drop(file);
+
+        // Contents to write instead of copying the file as-is.
+        let mut rewritten = None;
+        let is_jpeg = matches!(ext.as_deref(), Some("jpg") | Some("jpeg"));
+        let is_jpeg_or_mpo = is_jpeg || ext.as_deref() == Some("mpo");
+        if args.embed_date && date_source != DateSource::Exif {
+            if !is_jpeg {
+                if args.verbose {
+                    eprintln!("{path:?}: not a JPEG file, not adding an EXIF date");
+                }
+            } else if exif_missing {
+                let embedded = std::fs::read(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| jpeg::embed_date(&data, &datetime));
+                match embedded {
+                    Ok(data) => rewritten = Some(data),
+                    Err(e) => eprintln!("{path:?}: couldn't add an EXIF date, copying it unmodified: {e:#}"),
+                }
+            }
+        }
+
+        // JPEG files are all looked at, since their XMP data may have a location even if their
+        // EXIF data doesn't.
+        if args.strip_gps && (is_jpeg_or_mpo || maybe_exif.as_ref().is_some_and(jpeg::has_gps)) {
+            let stripped = if is_jpeg_or_mpo {
+                match &rewritten {
+                    Some(data) => jpeg::strip_gps(data),
+                    None => std::fs::read(path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|data| jpeg::strip_gps(&data)),
+                }
+            } else {
+                Err(anyhow!("removing GPS tags is only supported for JPEG files"))
+            };
+            match stripped {
+                Ok(Some(data)) => rewritten = Some(data),
+                Ok(None) => (),
+                Err(e) if args.strict.is_some() => {
+                    eprintln!("{path:?}: couldn't remove GPS tags, skipping because of --strict: {e:#}");
+                    failed.push(path.to_owned());
+                    continue;
+                }
+                Err(e) => eprintln!("{path:?}: couldn't remove GPS tags, copying it unmodified: {e:#}"),
+            }
+        }
+
+        let result = stats.time(Stage::Copy, || match &rewritten {
+            Some(data) => write_new_file(&new_path, data)
+                .and_then(|()| if args.move_files { std::fs::remove_file(path) } else { Ok(()) })
+                .map(|()| data.len() as u64),
+            None if args.move_files => move_file(path, &new_path),
+            None => copy_file(path, &new_path),
+        });
+        match result {
+            Ok(bytes) => {
+                if let (Some(index), Some(hash), None) = (&mut hash_index, &src_hash, &rewritten) {
+                    index.insert(&new_path, hash);
+                }
+                if let Some(free_space) = &mut free_space {
+                    free_space.wrote(bytes);
+                }
+                stats.add_bytes(Stage::Copy, bytes);
+                taken.insert(&new_path, path);
+                if args.prune_empty_dirs {
+                    moved.insert(path.to_owned());
+                }
+                if let Some(owner) = args.chown {
+                    if let Err(e) = owner::apply(owner, &new_path) {
+                        eprintln!("failed to set the owner of {new_path:?}: {e}");
+                        failed.push(path.to_owned());
+                    }
+                }
+                if let Some(histogram) = &mut histogram {
+                    histogram.add(datetime.year, datetime.month, bytes, date_source == DateSource::Mtime);
+                }
+                copied += 1;
+                copied_bytes += bytes;
+                if args.print0 {
+                    print0(path, &new_path)?;
+                }
+                if mark_processed {
+                    if let Err(e) = marker::mark(path, &new_path, &run_started) {
+                        if marker::is_unsupported(&e) {
+                            eprintln!("can't mark {path:?} as processed: {e}; not marking any more files");
+                            mark_processed = false;
+                        } else {
+                            eprintln!("failed to mark {path:?} as processed: {e}");
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to copy {path:?} to {new_path:?}: {e}");
+                failed.push(path.to_owned());
+                continue;
+            }
+        }
+
+        if let Err(e) = origin::record(args.record_origin, &new_path, path, date_source.as_str()) {
+            eprintln!("{e:?}");
+        }
+
+        if args.move_files {
+            moved_sidecars.extend(sidecars.iter().map(|(src, _)| src.clone()));
+        }
+        for (src, dst) in &sidecars {
+            let result = if taken.contains(dst) {
+                Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination already exists"))
+            } else if args.move_files {
+                move_file(src, dst)
+            } else {
+                copy_file(src, dst)
+            };
+            match result {
+                Ok(bytes) => {
+                    taken.insert(dst, src);
+                    if args.prune_empty_dirs {
+                        moved.insert(src.to_owned());
+                    }
+                    if let Some(owner) = args.chown {
+                        if let Err(e) = owner::apply(owner, dst) {
+                            eprintln!("failed to set the owner of {dst:?}: {e}");
+                            failed.push(src.to_owned());
+                        }
+                    }
+                    copied_bytes += bytes;
+                    if args.print0 {
+                        print0(src, dst)?;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to copy {src:?} to {dst:?}: {e}");
+                    failed.push(src.to_owned());
+                }
+            }
+        }
+    }
+
+    if interrupted {
+        eprintln!("interrupted");
+    }
+    if let (Some(index), Some(index_path), false) = (&mut hash_index, &args.hash_index, args.verify_only) {
+        if let Err(e) = index.save(index_path) {
+            eprintln!("{e:?}");
+        }
+    }
+
+    let mut pruned_empty = 0u64;
+    for dir in prune::empty_dirs(&args.src, &moved) {
+        if let Some(script) = &mut script {
+            script.rmdir(&dir)?;
+        } else if dry_run {
+            eprintln!("would remove empty directory {dir:?}");
+        } else if let Err(e) = std::fs::remove_dir(&dir) {
+            eprintln!("failed to remove empty directory {dir:?}: {e}");
+            continue;
+        }
+        pruned_empty += 1;
+    }
+    if let Some(script) = script {
+        script.finish()?;
+    }
+    if let (true, Some(diff)) = (args.verify_extra, &mut diff) {
+        diff.set_extra(unexpected_files(&args, &expected));
+    }
+    if let Some(diff) = &diff {
+        diff.print(args.verbose);
+        if let Some(dir) = &args.diff_files {
+            if let Err(e) = diff.write(dir) {
+                eprintln!("{e:?}");
+            }
+        }
+    }
+    if dry_run {
+        eprintln!("{copied} files would be copied");
+    } else {
+        eprintln!("{copied} files copied ({copied_bytes} bytes), {} failed", failed.len());
+    }
+    if dedupe {
+        eprintln!("{duplicates} duplicates");
+    }
+    if args.skip_same.is_some() {
+        eprintln!("{quick_skipped} files skipped by --skip-same (contents not compared)");
+    }
+    if other_camera > 0 {
+        eprintln!("{other_camera} files from other cameras skipped");
+    }
+    if args.clear_markers && dry_run {
+        eprintln!("{marked} marks would be removed");
+    } else if args.clear_markers {
+        eprintln!("{marked} marks removed");
+    } else if marked > 0 {
+        eprintln!("{marked} files skipped for being marked as processed");
+    }
+    if args.prune_empty_dirs && dry_run {
+        eprintln!("{pruned_empty} empty source directories would be removed");
+    } else if args.prune_empty_dirs {
+        eprintln!("{pruned_empty} empty source directories removed");
+    }
+    let pruned_dirs = pruned_dirs.load(Ordering::Relaxed);
+    if pruned_dirs > 0 {
+        eprintln!("{pruned_dirs} excluded directories skipped");
+    }
+    let ignored = ignored.load(Ordering::Relaxed);
+    if ignored > 0 {
+        eprintln!("{ignored} files and directories skipped by ignore patterns");
+    }
+    if !empty.is_empty() {
+        eprintln!("{} empty files skipped:", empty.len());
+        for path in &empty {
+            eprintln!("    {path:?}");
+        }
+    }
+    if !too_big.is_empty() {
+        eprintln!("{} files skipped for being too big for FAT32:", too_big.len());
+        for path in &too_big {
+            eprintln!("    {path:?}");
+        }
+    }
+    if !too_new.is_empty() {
+        eprintln!("{} files skipped for being modified too recently:", too_new.len());
+        for path in &too_new {
+            eprintln!("    {path:?}");
+        }
+    }
+    if let (Some(report), Some(report_path)) = (&duplicate_report, &args.report_duplicates) {
+        if let Err(e) = report.write(report_path) {
+            eprintln!("{e:?}");
+        }
+    }
+    if !dater.suspect.is_empty() {
+        eprintln!("{} files with suspect EXIF dates:", dater.suspect.len());
+        for path in &dater.suspect {
+            eprintln!("    {path:?}");
+        }
+    }
+    if walk_errors > 0 {
+        eprintln!("{walk_errors} errors reading the source tree");
+    }
+    if !failed.is_empty() {
+        eprintln!("failed files:");
+        for path in &failed {
+            eprintln!("    {path:?}");
+        }
+    }
+    if let Some(histogram) = &histogram {
+        histogram.print();
+    }
+    stats.print();
+    if interrupted {
+        return Ok(ExitCode::from(EXIT_INTERRUPTED));
+    }
+    if low_space {
+        return Ok(ExitCode::from(EXIT_LOW_SPACE));
+    }
+    if too_many_errors {
+        return Ok(ExitCode::from(EXIT_TOO_MANY_ERRORS));
+    }
+    if !failed.is_empty() || walk_errors > 0 {
+        return Ok(ExitCode::FAILURE);
+    }
+    if args.verify_only && !diff.as_ref().is_none_or(Diff::is_clean) {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    fn parse_args(extra: &[&str]) -> Args {
+        let base = ["cu_backfill", "--src", "src", "--dst", "dst", "--timezone", "UTC"];
+        Args::parse_from(base.iter().chain(extra))
+    }
+
+    /// Date a file, with the EXIF data of JPEG files read the way the main loop does.
+    fn date(
+        dater: &mut Dater,
+        path: &Path,
+        kept: Option<DateTime>,
+    ) -> anyhow::Result<(String, DateSource, Option<u32>)> {
+        let file = File::open(path).unwrap();
+        let meta = file.metadata().unwrap();
+        let ext = path.extension().and_then(OsStr::to_str).map(str::to_ascii_lowercase);
+        let exif = match ext.as_deref() {
+            Some("jpg") => read_exif(&file, "jpg").ok().or_else(|| jpeg::resync_exif(&file).ok().flatten()),
+            _ => None,
+        };
+        let undated = Undated {
+            path,
+            file: &file,
+            meta: &meta,
+            ext: ext.as_deref(),
+            exif: exif.as_ref(),
+            kept,
+            bad_metadata: None,
+        };
+        let (dt, source, chapter) = dater.date(&mut Stats::new(false), undated)?;
+        Ok((dt.to_string(), source, chapter.map(|c| c.number)))
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn date_from_exif() {
+        let args = parse_args(&[]);
+        let mut dater = Dater::new(&args, None);
+        let (dt, source, chapter) = date(&mut dater, &fixture("xmp-before-exif.jpg"), None).unwrap();
+        assert_eq!(dt, "2019-04-02 10:11:12");
+        assert_eq!(source, DateSource::Exif);
+        assert_eq!(chapter, None);
+    }
+
+    #[test]
+    fn kept_name_wins() {
+        let args = parse_args(&[]);
+        let mut dater = Dater::new(&args, None);
+        let kept = filename::filename_datetime("2017-09-03 14.22.10");
+        let (dt, source, _) = date(&mut dater, &fixture("xmp-before-exif.jpg"), kept).unwrap();
+        assert_eq!(dt, "2017-09-03 14:22:10");
+        assert_eq!(source, DateSource::Filename);
+    }
+
+    #[test]
+    fn later_chapters_use_the_first_chapters_time() {
+        let dir = temp_dir("chapters");
+        let (first, second) = (dir.join("GX010123.MP4"), dir.join("GX020123.MP4"));
+        std::fs::copy(fixture("mvhd.mp4"), &first).unwrap();
+        std::fs::copy(fixture("mvhd.mp4"), &second).unwrap();
+        let args = parse_args(&[]);
+        let mut dater = Dater::new(&args, None);
+
+        let dated = date(&mut dater, &first, None).unwrap();
+        assert_eq!(dated, ("2018-08-09 07:06:05".to_owned(), DateSource::Video, Some(1)));
+        // As with --move, the first chapter is gone by the time the second comes up.
+        std::fs::remove_file(&first).unwrap();
+        let dated = date(&mut dater, &second, None).unwrap();
+        assert_eq!(dated, ("2018-08-09 07:06:05".to_owned(), DateSource::Video, Some(2)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_the_name_unless_strict() {
+        let dir = temp_dir("fallback");
+        let path = dir.join("2017-09-03 14.22.10.txt");
+        std::fs::write(&path, "no metadata here").unwrap();
+
+        let args = parse_args(&[]);
+        let mut dater = Dater::new(&args, None);
+        let (dt, source, _) = date(&mut dater, &path, None).unwrap();
+        assert_eq!(dt, "2017-09-03 14:22:10");
+        assert_eq!(source, DateSource::Filename);
+
+        let strict = parse_args(&["--strict=all"]);
+        let mut dater = Dater::new(&strict, None);
+        assert!(date(&mut dater, &path, None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,56 @@
+//! Marking source files as processed, in an extended attribute, for --mark-processed.
+
+use std::io;
+use std::path::Path;
+
+const XATTR_DONE: &str = "user.cu_backfill.done";
+
+/// Whether the file has been marked as processed by an earlier run.
+#[cfg(unix)]
+pub fn is_marked(path: &Path) -> bool {
+    matches!(xattr::get(path, XATTR_DONE), Ok(Some(_)))
+}
+
+#[cfg(not(unix))]
+pub fn is_marked(_path: &Path) -> bool {
+    false
+}
+
+/// Mark the file as processed: copied to `dst` by the run that started at `time`.
+#[cfg(unix)]
+pub fn mark(path: &Path, dst: &Path, time: &chrono::DateTime<chrono::Utc>) -> io::Result<()> {
+    let json = serde_json::json!({
+        "destination": dst.to_string_lossy(),
+        "time": time.to_rfc3339(),
+    });
+    xattr::set(path, XATTR_DONE, json.to_string().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn mark(_path: &Path, _dst: &Path, _time: &chrono::DateTime<chrono::Utc>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are only supported on Unix"))
+}
+
+/// Remove the mark from a file, returning whether it had one.
+#[cfg(unix)]
+pub fn clear(path: &Path) -> io::Result<bool> {
+    if !is_marked(path) {
+        return Ok(false);
+    }
+    xattr::remove(path, XATTR_DONE).map(|()| true)
+}
+
+#[cfg(not(unix))]
+pub fn clear(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Whether an error means the filesystem can't store extended attributes at all, so there's no
+/// point trying again on other files.
+pub fn is_unsupported(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error().is_some_and(|n| n == libc::ENOTSUP || n == libc::EOPNOTSUPP) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::Unsupported
+}
@@ -0,0 +1,151 @@
+//! Recording, on each copied file, where it was copied from.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow};
+use clap::{Parser, ValueEnum};
+
+const XATTR_ORIGIN: &str = "user.cu_backfill.origin";
+const XATTR_DATE_SOURCE: &str = "user.cu_backfill.date_source";
+pub const SIDECAR_SUFFIX: &str = ".origin.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordOrigin {
+    /// In extended attributes on the copied file (Unix only).
+    Xattr,
+    /// In a "<name>.origin.json" file next to the copied file.
+    Sidecar,
+    /// Don't record it.
+    None,
+}
+
+/// Look up where a file was copied from, as recorded by --record-origin.
+#[derive(Debug, Parser)]
+#[command(name = "cu_backfill where")]
+pub struct WhereArgs {
+    /// A file in the destination tree.
+    file: PathBuf,
+}
+
+pub struct Origin {
+    pub path: PathBuf,
+    pub date_source: Option<String>,
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(SIDECAR_SUFFIX);
+    s.into()
+}
+
+/// Whether this is a sidecar file written by us, which should never be treated as a photo.
+pub fn is_sidecar(path: &Path) -> bool {
+    path.as_os_str().to_string_lossy().ends_with(SIDECAR_SUFFIX)
+}
+
+/// Record on `dst` that it was copied from `origin`, and where its date came from.
+pub fn record(mode: RecordOrigin, dst: &Path, origin: &Path, date_source: &str) -> anyhow::Result<()> {
+    let origin = std::fs::canonicalize(origin).unwrap_or_else(|_| origin.to_owned());
+    match mode {
+        RecordOrigin::None => Ok(()),
+        RecordOrigin::Xattr => set_xattrs(dst, &origin, date_source),
+        RecordOrigin::Sidecar => {
+            let json = serde_json::json!({
+                "origin": origin.to_string_lossy(),
+                "date_source": date_source,
+            });
+            let sidecar = sidecar_path(dst);
+            std::fs::write(&sidecar, serde_json::to_string_pretty(&json)? + "\n")
+                .with_context(|| format!("failed to write {sidecar:?}"))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_xattrs(dst: &Path, origin: &Path, date_source: &str) -> anyhow::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    xattr::set(dst, XATTR_ORIGIN, origin.as_os_str().as_bytes())
+        .and_then(|()| xattr::set(dst, XATTR_DATE_SOURCE, date_source.as_bytes()))
+        .with_context(|| format!("failed to set extended attributes on {dst:?}"))
+}
+
+#[cfg(not(unix))]
+fn set_xattrs(_dst: &Path, _origin: &Path, _date_source: &str) -> anyhow::Result<()> {
+    anyhow::bail!("extended attributes are only supported on Unix")
+}
+
+#[cfg(unix)]
+fn get_xattrs(path: &Path) -> Option<Origin> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    // Filesystems without extended attributes (FAT, exFAT, SMB shares) fail here; the sidecar is
+    // what's used on those.
+    let origin = xattr::get(path, XATTR_ORIGIN).ok().flatten()?;
+    let date_source = xattr::get(path, XATTR_DATE_SOURCE)
+        .ok()
+        .flatten()
+        .map(|v| String::from_utf8_lossy(&v).into_owned());
+    Some(Origin {
+        path: OsStr::from_bytes(&origin).into(),
+        date_source,
+    })
+}
+
+#[cfg(not(unix))]
+fn get_xattrs(_path: &Path) -> Option<Origin> {
+    None
+}
+
+/// Find where a file was copied from, from either its extended attributes or its sidecar.
+pub fn lookup(path: &Path) -> anyhow::Result<Origin> {
+    if let Some(origin) = get_xattrs(path) {
+        return Ok(origin);
+    }
+
+    let sidecar = sidecar_path(path);
+    let json = match std::fs::read_to_string(&sidecar) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("no origin is recorded for {path:?}"));
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read {sidecar:?}")),
+    };
+    let value: serde_json::Value = serde_json::from_str(&json)
+        .with_context(|| format!("failed to parse {sidecar:?}"))?;
+    let origin = value["origin"].as_str()
+        .ok_or_else(|| anyhow!("{sidecar:?} has no origin"))?;
+    Ok(Origin {
+        path: origin.into(),
+        date_source: value["date_source"].as_str().map(str::to_owned),
+    })
+}
+
+pub fn where_main(args: WhereArgs) -> anyhow::Result<()> {
+    let origin = lookup(&args.file)?;
+    println!("{}", origin.path.display());
+    if let Some(source) = origin.date_source {
+        println!("date from: {source}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_falls_back_to_the_sidecar() {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-origin-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let copy = dir.join("2019-04-02 10.11.12.jpg");
+        std::fs::write(&copy, b"").unwrap();
+
+        assert!(lookup(&copy).is_err());
+        record(RecordOrigin::Sidecar, &copy, Path::new("/photos/IMG_1234.jpg"), "EXIF").unwrap();
+        let origin = lookup(&copy).unwrap();
+        assert_eq!(origin.path, Path::new("/photos/IMG_1234.jpg"));
+        assert_eq!(origin.date_source.as_deref(), Some("EXIF"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
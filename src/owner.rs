@@ -0,0 +1,94 @@
+//! Setting the owner and group of created files, for --chown.
+
+use std::io;
+use std::path::Path;
+
+/// A user and/or group to give files to.
+#[derive(Debug, Clone, Copy)]
+pub struct Owner {
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(u32, u32), String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("invalid user name {name:?}"))?;
+    // SAFETY: the name is NUL-terminated; the result is copied out before anything else can
+    // call getpwnam.
+    let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if pw.is_null() {
+        return Err(format!("no such user {name:?}"));
+    }
+    // SAFETY: checked for null above.
+    Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) })
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("invalid group name {name:?}"))?;
+    // SAFETY: as for getpwnam above.
+    let gr = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if gr.is_null() {
+        return Err(format!("no such group {name:?}"));
+    }
+    // SAFETY: checked for null above.
+    Ok(unsafe { (*gr).gr_gid })
+}
+
+/// Parse and resolve `USER[:GROUP]`, `USER:` (the user's login group), or `:GROUP`. Names may
+/// also be numeric IDs.
+#[cfg(unix)]
+pub fn parse(spec: &str) -> Result<Owner, String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+
+    let (uid, login_gid) = match user {
+        "" => (None, None),
+        _ => match user.parse::<u32>() {
+            Ok(uid) => (Some(uid), None),
+            Err(_) => lookup_user(user).map(|(uid, gid)| (Some(uid), Some(gid)))?,
+        },
+    };
+    let gid = match group {
+        None => None,
+        Some("") if uid.is_some() => {
+            Some(login_gid.ok_or("a numeric user ID has no login group; give one after ':'")?)
+        }
+        Some("") => return Err("no user or group given".to_owned()),
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => Some(gid),
+            Err(_) => Some(lookup_group(group)?),
+        },
+    };
+
+    // SAFETY: geteuid can't fail.
+    let euid = unsafe { libc::geteuid() };
+    if uid.is_some_and(|uid| uid != euid) && euid != 0 {
+        return Err("giving files to another user needs root".to_owned());
+    }
+    Ok(Owner { uid, gid })
+}
+
+#[cfg(not(unix))]
+pub fn parse(_spec: &str) -> Result<Owner, String> {
+    Ok(Owner { uid: None, gid: None })
+}
+
+/// Give a file or directory to the owner.
+#[cfg(unix)]
+pub fn apply(owner: Owner, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::chown(path, owner.uid, owner.gid).map_err(|e| {
+        if e.kind() == io::ErrorKind::PermissionDenied {
+            io::Error::new(e.kind(), format!("not permitted to change the owner of {path:?} ({e})"))
+        } else {
+            e
+        }
+    })
+}
+
+#[cfg(not(unix))]
+pub fn apply(_owner: Owner, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Checking that the source and destination are usable before doing anything.
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Check that `src` can be read and `dst` can be written to (creating it, unless this is a dry
+/// run), returning a description of each problem found.
+pub fn check(src: &Path, dst: &Path, dry_run: bool) -> Vec<String> {
+    let mut problems = vec![];
+
+    match std::fs::metadata(src) {
+        Ok(meta) if !meta.is_dir() => problems.push(format!("--src {src:?} is not a directory")),
+        Ok(_) => {
+            if let Err(e) = std::fs::read_dir(src) {
+                problems.push(format!("--src {src:?} can't be read ({e}); check its permissions"));
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            problems.push(format!("--src {src:?} doesn't exist; check the path, and that its drive is mounted"));
+        }
+        Err(e) => problems.push(format!("--src {src:?} can't be accessed ({e})")),
+    }
+
+    match std::fs::metadata(dst) {
+        Ok(meta) if !meta.is_dir() => {
+            problems.push(format!("--dst {dst:?} exists but is not a directory"));
+            return problems;
+        }
+        Ok(_) => (),
+        // A dry run doesn't need the destination to exist yet.
+        Err(e) if e.kind() == ErrorKind::NotFound && dry_run => return problems,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if let Err(e) = std::fs::create_dir_all(dst) {
+                problems.push(format!("--dst {dst:?} doesn't exist and couldn't be created ({e})"));
+                return problems;
+            }
+        }
+        Err(e) => {
+            problems.push(format!("--dst {dst:?} can't be accessed ({e})"));
+            return problems;
+        }
+    }
+
+    if !dry_run {
+        let probe = dst.join(format!(".cu_backfill.probe.{}", std::process::id()));
+        let result = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .and_then(|_| std::fs::remove_file(&probe));
+        if let Err(e) = result {
+            problems.push(format!(
+                "--dst {dst:?} isn't writable ({e}); check its permissions, and that it isn't mounted read-only"));
+        }
+    }
+
+    problems
+}
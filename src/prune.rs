@@ -0,0 +1,31 @@
+//! Finding source directories left empty by --move, for --prune-empty-dirs.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Directories under `root` (but not `root` itself) that contain nothing but `moved` files and
+/// other such directories, deepest first so they can be removed in order.
+pub fn empty_dirs(root: &Path, moved: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    let mut candidates = moved.iter()
+        .flat_map(|path| path.ancestors().skip(1).take_while(|dir| *dir != root && dir.starts_with(root)))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then(a.cmp(b)));
+    candidates.dedup();
+
+    let mut empty = HashSet::new();
+    let mut result = vec![];
+    for dir in candidates {
+        let Ok(mut entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let all_gone = entries.all(|entry| entry.is_ok_and(|e| {
+            let path = e.path();
+            moved.contains(&path) || empty.contains(&path)
+        }));
+        if all_gone {
+            empty.insert(dir.to_owned());
+            result.push(dir.to_owned());
+        }
+    }
+    result
+}
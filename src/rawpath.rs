@@ -0,0 +1,29 @@
+//! Converting paths to and from bytes, losslessly where the platform allows it.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// The path as bytes: its raw OS bytes on Unix, or UTF-8 elsewhere.
+#[cfg(unix)]
+pub fn to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+pub fn to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    path.to_string_lossy().into_owned().into_bytes().into()
+}
+
+/// A path from bytes: raw OS bytes on Unix, or (lossily) UTF-8 elsewhere.
+#[cfg(unix)]
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
@@ -0,0 +1,112 @@
+//! Writing the planned operations out as a shell script, for --emit-script.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::rawpath;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScriptFormat {
+    /// POSIX shell.
+    Sh,
+    /// PowerShell.
+    Powershell,
+}
+
+pub struct Script {
+    out: BufWriter<File>,
+    format: ScriptFormat,
+    created_dirs: HashSet<PathBuf>,
+}
+
+impl Script {
+    pub fn create(path: &Path, format: ScriptFormat) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ScriptFormat::Sh => out.write_all(b"#!/bin/sh\nset -eu\n\n")?,
+            ScriptFormat::Powershell => out.write_all(b"$ErrorActionPreference = 'Stop'\n\n")?,
+        }
+        Ok(Self { out, format, created_dirs: HashSet::new() })
+    }
+
+    fn quoted(&mut self, path: &Path) -> io::Result<()> {
+        match self.format {
+            ScriptFormat::Sh => {
+                // Everything is literal inside single quotes, including newlines; a single quote
+                // itself has to be closed, escaped, and reopened.
+                self.out.write_all(b"'")?;
+                for &b in rawpath::to_bytes(path).iter() {
+                    if b == b'\'' {
+                        self.out.write_all(b"'\\''")?;
+                    } else {
+                        self.out.write_all(&[b])?;
+                    }
+                }
+                self.out.write_all(b"'")
+            }
+            ScriptFormat::Powershell => {
+                let s = path.to_string_lossy().replace('\'', "''");
+                write!(self.out, "'{s}'")
+            }
+        }
+    }
+
+    fn mkdir(&mut self, dir: &Path) -> io::Result<()> {
+        if !self.created_dirs.insert(dir.to_owned()) {
+            return Ok(());
+        }
+        match self.format {
+            ScriptFormat::Sh => {
+                self.out.write_all(b"mkdir -p -- ")?;
+                self.quoted(dir)?;
+            }
+            ScriptFormat::Powershell => {
+                self.out.write_all(b"New-Item -ItemType Directory -Force -Path ")?;
+                self.quoted(dir)?;
+                self.out.write_all(b" | Out-Null")?;
+            }
+        }
+        self.out.write_all(b"\n")
+    }
+
+    /// Add a command copying (or moving) `src` to `dst`, creating the destination's directory
+    /// first if needed.
+    pub fn copy(&mut self, src: &Path, dst: &Path, move_file: bool) -> io::Result<()> {
+        if let Some(dir) = dst.parent() {
+            self.mkdir(dir)?;
+        }
+        let command: &[u8] = match (self.format, move_file) {
+            (ScriptFormat::Sh, false) => b"cp -- ",
+            (ScriptFormat::Sh, true) => b"mv -- ",
+            (ScriptFormat::Powershell, false) => b"Copy-Item -LiteralPath ",
+            (ScriptFormat::Powershell, true) => b"Move-Item -LiteralPath ",
+        };
+        self.out.write_all(command)?;
+        self.quoted(src)?;
+        if self.format == ScriptFormat::Powershell {
+            self.out.write_all(b" -Destination")?;
+        }
+        self.out.write_all(b" ")?;
+        self.quoted(dst)?;
+        self.out.write_all(b"\n")
+    }
+
+    /// Add a command removing an empty directory.
+    pub fn rmdir(&mut self, dir: &Path) -> io::Result<()> {
+        let command: &[u8] = match self.format {
+            ScriptFormat::Sh => b"rmdir -- ",
+            ScriptFormat::Powershell => b"Remove-Item -LiteralPath ",
+        };
+        self.out.write_all(command)?;
+        self.quoted(dir)?;
+        self.out.write_all(b"\n")
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,69 @@
+//! Keeping an eye on the free space left in the destination, for --min-free.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long a measurement of free space is trusted before the filesystem is asked again.
+const RECHECK_AFTER: Duration = Duration::from_secs(5);
+
+/// Parse a size like "500M" or "5G" (binary units; a plain number is bytes).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'B' => 0,
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(format!("unknown size suffix {c:?}")),
+            };
+            (&s[..i], 1u64 << shift)
+        }
+        _ => (s, 1),
+    };
+    let n: u64 = digits.trim().parse().map_err(|e| format!("invalid size {s:?}: {e}"))?;
+    n.checked_mul(multiplier).ok_or_else(|| format!("size {s:?} is too big"))
+}
+
+/// Tracks the free space on the destination filesystem.
+pub struct FreeSpace {
+    path: PathBuf,
+    min: u64,
+    /// Last measurement, and how many bytes have been written since then.
+    last: Option<(Instant, u64, u64)>,
+}
+
+impl FreeSpace {
+    pub fn new(path: PathBuf, min: u64) -> Self {
+        Self { path, min, last: None }
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// Whether writing `len` more bytes would leave less than the minimum free.
+    pub fn would_exceed(&mut self, len: u64) -> io::Result<bool> {
+        let available = match self.last {
+            Some((when, available, written)) if when.elapsed() < RECHECK_AFTER => {
+                available.saturating_sub(written)
+            }
+            _ => {
+                let available = fs2::available_space(&self.path)?;
+                self.last = Some((Instant::now(), available, 0));
+                available
+            }
+        };
+        Ok(available.saturating_sub(len) < self.min)
+    }
+
+    /// Account for bytes written since the last measurement.
+    pub fn wrote(&mut self, len: u64) {
+        if let Some((_, _, written)) = &mut self.last {
+            *written += len;
+        }
+    }
+}
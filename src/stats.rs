@@ -0,0 +1,70 @@
+//! Timing of each stage of a run, for --stats.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Walk,
+    Metadata,
+    Hash,
+    Copy,
+}
+
+const STAGES: [(Stage, &str); 4] = [
+    (Stage::Walk, "walk"),
+    (Stage::Metadata, "metadata"),
+    (Stage::Hash, "hash"),
+    (Stage::Copy, "copy"),
+];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct StageStats {
+    time: Duration,
+    count: u64,
+    bytes: u64,
+}
+
+/// Cumulative wall time, number of operations, and bytes processed in each stage.
+///
+/// When disabled, nothing is measured at all.
+#[derive(Debug, Default)]
+pub struct Stats {
+    enabled: bool,
+    stages: [StageStats; STAGES.len()],
+}
+
+impl Stats {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, ..Default::default() }
+    }
+
+    /// Run `f`, adding the time it took to the given stage.
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let s = &mut self.stages[stage as usize];
+        s.time += start.elapsed();
+        s.count += 1;
+        result
+    }
+
+    pub fn add_bytes(&mut self, stage: Stage, bytes: u64) {
+        if self.enabled {
+            self.stages[stage as usize].bytes += bytes;
+        }
+    }
+
+    pub fn print(&self) {
+        if !self.enabled {
+            return;
+        }
+        eprintln!("{:<10} {:>12} {:>10} {:>15}", "stage", "time", "count", "bytes");
+        for (stage, name) in STAGES {
+            let s = &self.stages[stage as usize];
+            eprintln!("{:<10} {:>11.3}s {:>10} {:>15}", name, s.time.as_secs_f64(), s.count, s.bytes);
+        }
+    }
+}
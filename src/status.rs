@@ -0,0 +1,100 @@
+//! Reporting progress when sent SIGUSR1, and pausing between files when sent SIGUSR2.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often to check whether to resume or stop while paused.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How far a run has got, as of the start of the current file.
+#[derive(Debug)]
+pub struct Progress {
+    pub done: u64,
+    /// Number of files to do, if known up front (it isn't when walking the tree).
+    pub total: Option<u64>,
+    pub bytes: u64,
+    pub errors: u64,
+    pub current: Option<PathBuf>,
+    start: Instant,
+}
+
+impl Progress {
+    fn print(&self) {
+        let mut msg = match self.total {
+            Some(total) => format!("{} of {total} files done", self.done),
+            None => format!("{} files done", self.done),
+        };
+        msg += &format!(", {} bytes copied, {} errors", self.bytes, self.errors);
+        if let Some(current) = &self.current {
+            msg += &format!(", working on {current:?}");
+        }
+        if let (Some(total), true) = (self.total, self.done > 0) {
+            let left = self.start.elapsed().as_secs_f64() / self.done as f64 * total.saturating_sub(self.done) as f64;
+            msg += &format!(", about {left:.0}s left");
+        }
+        eprintln!("status: {msg}");
+    }
+}
+
+pub struct Status {
+    progress: Arc<Mutex<Progress>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl Status {
+    /// Start listening for the signals. On platforms without them, this does nothing.
+    pub fn start(total: Option<u64>) -> io::Result<Self> {
+        let progress = Arc::new(Mutex::new(Progress {
+            done: 0,
+            total,
+            bytes: 0,
+            errors: 0,
+            current: None,
+            start: Instant::now(),
+        }));
+        let paused = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        {
+            use signal_hook::consts::{SIGUSR1, SIGUSR2};
+            let mut signals = signal_hook::iterator::Signals::new([SIGUSR1, SIGUSR2])?;
+            let progress = Arc::clone(&progress);
+            let paused = Arc::clone(&paused);
+            std::thread::spawn(move || {
+                for sig in signals.forever() {
+                    match sig {
+                        SIGUSR1 => {
+                            if let Ok(progress) = progress.lock() {
+                                progress.print();
+                            }
+                        }
+                        SIGUSR2 => {
+                            if paused.fetch_xor(true, Ordering::Relaxed) {
+                                eprintln!("resuming");
+                            } else {
+                                eprintln!("pausing after the current file; send SIGUSR2 again to resume");
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            });
+        }
+        Ok(Self { progress, paused })
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Progress)) {
+        if let Ok(mut progress) = self.progress.lock() {
+            f(&mut progress);
+        }
+    }
+
+    /// While paused, wait to be resumed, or for `stop` to be set.
+    pub fn wait_while_paused(&self, stop: &AtomicBool) {
+        while self.paused.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Keeping track of which destination names are taken, without asking the filesystem about
+//! each candidate name.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// A name as it's compared: in NFC and lowercase, so that names which some filesystems treat as
+/// one (composed and decomposed forms, or different cases) are the same. On a filesystem that
+/// tells them apart, this only costs a suffix now and then.
+fn key(name: &OsStr) -> OsString {
+    match name.to_str() {
+        Some(s) => s.nfc().flat_map(char::to_lowercase).collect::<String>().into(),
+        None => name.to_ascii_lowercase(),
+    }
+}
+
+#[derive(Default)]
+struct Dir {
+    /// Names in the directory when it was listed, or `None` if it couldn't be listed, in which
+    /// case the filesystem is asked about each name instead.
+    existing: Option<HashSet<OsString>>,
+    /// Names given out during this run, and the source file each was given to.
+    planned: HashMap<OsString, PathBuf>,
+}
+
+/// Destination names that are taken, either by files already there, or by files this run has
+/// copied (or would copy, in a dry run). Each directory is listed once, the first time a file
+/// is headed there.
+#[derive(Default)]
+pub struct Taken {
+    dirs: HashMap<PathBuf, Dir>,
+}
+
+fn list(dir: &Path) -> Option<HashSet<OsString>> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    entries.map(|entry| entry.map(|e| key(&e.file_name()))).collect::<Result<_, _>>().ok()
+}
+
+impl Taken {
+    fn dir(&mut self, dir: &Path) -> &mut Dir {
+        self.dirs.entry(dir.to_owned()).or_insert_with(|| Dir {
+            existing: list(dir),
+            planned: HashMap::new(),
+        })
+    }
+
+    /// Whether a file by this name was in the destination before this run.
+    pub fn on_disk(&mut self, path: &Path) -> bool {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return path.exists();
+        };
+        match &self.dir(dir).existing {
+            Some(existing) => existing.contains(&key(name)),
+            None => path.exists(),
+        }
+    }
+
+    pub fn contains(&mut self, path: &Path) -> bool {
+        let planned = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => self.dir(dir).planned.contains_key(&key(name)),
+            _ => false,
+        };
+        planned || self.on_disk(path)
+    }
+
+    /// Give out a name to the source file `src`.
+    pub fn insert(&mut self, path: &Path, src: &Path) {
+        if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+            self.dir(dir).planned.insert(key(name), src.to_owned());
+        }
+    }
+
+    /// The source file a name was given to during this run, if it was.
+    pub fn planned_source(&mut self, path: &Path) -> Option<PathBuf> {
+        let (dir, name) = (path.parent()?, path.file_name()?);
+        self.dir(dir).planned.get(&key(name)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_differing_in_case_or_normalization_collide() {
+        let mut taken = Taken::default();
+        let dir = Path::new("/nonexistent/cu_backfill");
+        taken.insert(&dir.join("IMG_0001.JPG"), Path::new("a"));
+        assert!(taken.contains(&dir.join("img_0001.jpg")));
+        taken.insert(&dir.join("Cafe\u{301}.jpg"), Path::new("b"));
+        assert!(taken.contains(&dir.join("CAF\u{c9}.JPG")));
+        assert_eq!(taken.planned_source(&dir.join("caf\u{e9}.jpg")).as_deref(), Some(Path::new("b")));
+        assert!(!taken.contains(&dir.join("IMG_0002.JPG")));
+    }
+}
@@ -0,0 +1,163 @@
+//! The JSON files Google Takeout puts next to each photo and video, which say when it was taken.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use chrono::{TimeZone, Utc};
+
+/// Takeout cuts JSON file names down to this many characters, including the ".json".
+const MAX_NAME_CHARS: usize = 51;
+
+/// What Takeout adds to the media file's name, before the ".json" (newer exports use the second).
+const SUFFIXES: &[&str] = &["", ".supplemental-metadata"];
+
+/// Names that a JSON file for a media file named `name` (and a duplicate number, like the "(1)" in
+/// "IMG_1234(1).jpg") might have.
+fn candidate_names(name: &str, number: &str) -> Vec<String> {
+    let mut names = vec![];
+    for suffix in SUFFIXES {
+        let full = format!("{name}{suffix}");
+        let max = MAX_NAME_CHARS.saturating_sub(".json".len() + number.chars().count());
+        let cut = full.chars().take(max).collect::<String>();
+        for base in [full, cut] {
+            let candidate = format!("{base}{number}.json");
+            if !names.contains(&candidate) {
+                names.push(candidate);
+            }
+        }
+    }
+    names
+}
+
+/// Where the JSON file Takeout made for this file might be, most likely first.
+///
+/// Edited copies ("IMG_1234-edited.jpg") share the original's JSON file, and duplicates
+/// ("IMG_1234(1).jpg") have the number at the end of the JSON file's name instead
+/// ("IMG_1234.jpg(1).json").
+fn sidecar_candidates(path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = path.file_stem().and_then(OsStr::to_str) else {
+        return vec![];
+    };
+    let ext = path.extension().and_then(|e| e.to_str()).map_or(String::new(), |e| format!(".{e}"));
+    let stem = stem.strip_suffix("-edited").unwrap_or(stem);
+    let (stem, number) = match stem.rfind('(') {
+        Some(i) if stem.ends_with(')') && stem[i + 1..stem.len() - 1].bytes().all(|b| b.is_ascii_digit()) => {
+            (&stem[..i], &stem[i..])
+        }
+        _ => (stem, ""),
+    };
+    let mut names = candidate_names(&format!("{stem}{ext}"), number);
+    if !number.is_empty() {
+        // Some exports leave the number where it was.
+        names.extend(candidate_names(&format!("{stem}{number}{ext}"), ""));
+    }
+    names.into_iter().map(|name| path.with_file_name(name)).collect()
+}
+
+/// The JSON file Takeout made for this file, if there is one.
+pub fn sidecar(path: &Path) -> Option<PathBuf> {
+    sidecar_candidates(path).into_iter().find(|p| p.is_file())
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("json"))
+}
+
+/// Takeout's JSON files in a directory: the ones that some other file there has as its sidecar.
+fn list(dir: &Path) -> HashSet<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashSet::new();
+    };
+    let (jsons, others) = entries.filter_map(Result::ok)
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .partition::<Vec<_>, _>(|p| is_json(p));
+    let jsons = jsons.into_iter().collect::<HashSet<_>>();
+    others.iter()
+        .filter_map(|p| sidecar_candidates(p).into_iter().find(|json| jsons.contains(json)))
+        .collect()
+}
+
+/// Takeout's JSON files, which aren't copied themselves. Each directory is listed the first time
+/// any file in it comes up, before anything has been moved out of it, and again if files have
+/// been added since. A JSON file stays a sidecar once it has been seen to be one, even after its
+/// media file has been moved away.
+#[derive(Default)]
+pub struct Sidecars {
+    dirs: HashMap<PathBuf, (Option<SystemTime>, HashSet<PathBuf>)>,
+}
+
+impl Sidecars {
+    /// Whether this is the JSON file for another file next to it. A JSON file that nothing
+    /// points to is just a file.
+    ///
+    /// A file has to be passed in before it's moved, so that its directory is listed in time.
+    pub fn contains(&mut self, path: &Path) -> bool {
+        let Some(dir) = path.parent() else {
+            return false;
+        };
+        let modified = || std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+        let (listed, sidecars) = self.dirs.entry(dir.to_owned()).or_insert_with(|| (modified(), list(dir)));
+        if !is_json(path) {
+            return false;
+        }
+        if sidecars.contains(path) {
+            return true;
+        }
+        // Files may have come since (with --watch); the directory's mtime also changes when
+        // files are moved out of it, so sidecars found before are kept.
+        let now = modified();
+        if now != *listed {
+            *listed = now;
+            sidecars.extend(list(dir));
+        }
+        sidecars.contains(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_duplicate_number_doesnt_underflow() {
+        let number = format!("({})", "9".repeat(60));
+        let names = candidate_names("IMG_1234.jpg", &number);
+        assert!(names.contains(&format!("IMG_1234.jpg{number}.json")));
+    }
+
+    #[test]
+    fn json_is_a_sidecar_only_if_something_points_to_it() {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-takeout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        for name in ["IMG_1234.jpg", "IMG_1234.jpg.json", "IMG_1234(1).jpg", "IMG_1234.jpg(1).json", "notes.txt.json"] {
+            std::fs::write(dir.join(name), b"{}").unwrap();
+        }
+        let mut sidecars = Sidecars::default();
+        assert!(sidecars.contains(&dir.join("IMG_1234.jpg.json")));
+        assert!(sidecars.contains(&dir.join("IMG_1234.jpg(1).json")));
+        assert!(!sidecars.contains(&dir.join("notes.txt.json")));
+        assert!(!sidecars.contains(&dir.join("IMG_1234.jpg")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sidecar_stays_one_after_its_media_file_is_moved() {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-takeout-move-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("IMG_1234.jpg"), b"").unwrap();
+        std::fs::write(dir.join("IMG_1234.jpg.json"), b"{}").unwrap();
+
+        let mut sidecars = Sidecars::default();
+        // The walk comes to the photo first, and --move takes it away.
+        assert!(!sidecars.contains(&dir.join("IMG_1234.jpg")));
+        std::fs::remove_file(dir.join("IMG_1234.jpg")).unwrap();
+        assert!(sidecars.contains(&dir.join("IMG_1234.jpg.json")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Watching the source tree for new and changed files, for --watch.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+/// Longest time to wait for an event before checking whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Yields files under a directory once they have been created or modified and then left alone
+/// for the debounce window. Files that are gone by then are dropped.
+///
+/// Ends when `stop` is set.
+pub struct Watcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Files with changes not yet settled, and when each last changed.
+    pending: HashMap<PathBuf, Instant>,
+    ready: VecDeque<PathBuf>,
+    debounce: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    pub fn new(root: &Path, debounce: Duration, stop: Arc<AtomicBool>) -> notify::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+            debounce,
+            stop,
+        })
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                let now = Instant::now();
+                for path in event.paths {
+                    self.pending.insert(path, now);
+                }
+            }
+            EventKind::Remove(_) => {
+                for path in &event.paths {
+                    self.pending.remove(path);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Move files that haven't changed for the debounce window to the ready queue.
+    fn settle(&mut self) {
+        let now = Instant::now();
+        let debounce = self.debounce;
+        let ready = &mut self.ready;
+        self.pending.retain(|path, &mut changed| {
+            if now.duration_since(changed) < debounce {
+                return true;
+            }
+            if path.is_file() {
+                ready.push_back(path.clone());
+            } else if path.is_dir() {
+                // A directory moved in comes as one event, with nothing for the files in it.
+                push_files(path, ready);
+            }
+            false
+        });
+    }
+}
+
+/// Add the files under a directory to `ready`, in sorted order. Excluded and ignored ones are
+/// filtered out later, along with everything else the watcher yields.
+fn push_files(dir: &Path, ready: &mut VecDeque<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("failed to read new directory {dir:?}: {e}");
+            return;
+        }
+    };
+    let mut entries = entries.filter_map(Result::ok).collect::<Vec<_>>();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => push_files(&entry.path(), ready),
+            Ok(t) if t.is_file() => ready.push_back(entry.path()),
+            _ => (),
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(path) = self.ready.pop_front() {
+                return Some(Ok(path));
+            }
+
+            let timeout = self.pending.values()
+                .map(|&changed| (changed + self.debounce).saturating_duration_since(Instant::now()))
+                .min()
+                .map_or(POLL_INTERVAL, |t| t.min(POLL_INTERVAL));
+            match self.events.recv_timeout(timeout) {
+                Ok(Ok(event)) => self.handle(event),
+                Ok(Err(e)) => eprintln!("error watching for changes: {e}"),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+            self.settle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_in_a_new_directory_are_found() {
+        let dir = std::env::temp_dir().join(format!("cu_backfill-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("b/c")).unwrap();
+        for name in ["b/2.jpg", "a.jpg", "b/c/3.jpg", "b/1.jpg"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let mut ready = VecDeque::new();
+        push_files(&dir, &mut ready);
+        let expected = ["a.jpg", "b/1.jpg", "b/2.jpg", "b/c/3.jpg"].map(|name| dir.join(name));
+        assert_eq!(ready, expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+//! Helpers shared by the integration tests, which run the built binary on trees made in a
+//! temporary directory.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory under the system temporary directory, removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cu_backfill-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Copy a file from tests/fixtures to `dst`.
+pub fn fixture(name: &str, dst: &Path) {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    if let Some(dir) = dst.parent() {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    std::fs::copy(src, dst).unwrap();
+}
+
+/// Run cu_backfill from `src` to `dst` with some more arguments.
+pub fn run(src: &Path, dst: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cu_backfill"))
+        .arg("--src")
+        .arg(src)
+        .arg("--dst")
+        .arg(dst)
+        .args(args)
+        .output()
+        .unwrap()
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{TempDir, fixture, run};
+
+#[test]
+fn prints_only_the_plan() {
+    let tmp = TempDir::new("dry-run");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("xmp-before-exif.jpg", &src.join("a.jpg"));
+    fixture("xmp-before-exif.jpg", &src.join("b.jpg"));
+    std::fs::create_dir(&dst).unwrap();
+
+    let output = run(&src, &dst, &["--dry-run"]);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    // Both have the same date, so the second name is taken by the first, even though it was only
+    // planned.
+    let expected = format!("{:?} -> {:?}\n{:?} -> {:?}\n",
+        src.join("a.jpg"), dst.join("2019").join("2019-04-02 10.11.12.jpg"),
+        src.join("b.jpg"), dst.join("2019").join("2019-04-02 10.11.12-1.jpg"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    assert!(!dst.join("2019").exists());
+}
+
+#[test]
+fn dry_run_dedupe_skips_planned_duplicates() {
+    let tmp = TempDir::new("dry-run-dedupe");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("xmp-before-exif.jpg", &src.join("a.jpg"));
+    fixture("xmp-before-exif.jpg", &src.join("b.jpg"));
+    std::fs::create_dir(&dst).unwrap();
+
+    let output = run(&src, &dst, &["--dry-run", "--dedupe"]);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let expected = format!("{:?} -> {:?}\n", src.join("a.jpg"), dst.join("2019").join("2019-04-02 10.11.12.jpg"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
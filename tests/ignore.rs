@@ -0,0 +1,21 @@
+mod common;
+
+use std::process::Command;
+
+use common::{TempDir, fixture};
+
+#[test]
+fn why_with_dot_slash_src() {
+    let tmp = TempDir::new("ignore");
+    fixture("xmp-before-exif.jpg", &tmp.path().join("photos/skip/a.jpg"));
+    std::fs::write(tmp.path().join("photos/.cubackfillignore"), "skip/\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cu_backfill"))
+        .current_dir(tmp.path())
+        .args(["--src", "./photos", "--dst", "out", "--why", "skip/a.jpg"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("is skipped because of pattern \"skip/\""), "stdout: {stdout}");
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::{TempDir, fixture, run};
+
+#[test]
+fn shortened_names_can_still_be_written() {
+    let tmp = TempDir::new("long-names");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    // Not too long for the source, but too long to fit whole after the date.
+    let stem = "\u{1f4f7}".repeat(60);
+    fixture("xmp-before-exif.jpg", &src.join(format!("{stem}.jpg")));
+    std::fs::create_dir(&dst).unwrap();
+
+    let output = run(&src, &dst, &["--append-original-name", "--record-origin", "sidecar"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains("shortening the original name"), "stderr: {stderr}");
+
+    let names = std::fs::read_dir(dst.join("2019")).unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names.len(), 2, "{names:?}");
+    assert!(names.iter().all(|name| name.len() <= 255 && name.starts_with("2019-04-02 10.11.12 (\u{1f4f7}")));
+    assert!(names.iter().any(|name| name.ends_with(").jpg")));
+    assert!(names.iter().any(|name| name.ends_with(").jpg.origin.json")));
+}
@@ -0,0 +1,29 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{TempDir, fixture, run};
+
+const XATTR_DONE: &str = "user.cu_backfill.done";
+
+#[test]
+fn dry_run_leaves_markers_alone() {
+    let tmp = TempDir::new("markers");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    let photo = src.join("a.jpg");
+    fixture("xmp-before-exif.jpg", &photo);
+    std::fs::create_dir(&dst).unwrap();
+    if xattr::set(&photo, XATTR_DONE, b"{}").is_err() {
+        eprintln!("skipping: no extended attributes on {:?}", tmp.path());
+        return;
+    }
+
+    let output = run(&src, &dst, &["--clear-markers", "--dry-run"]);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 marks would be removed"));
+    assert!(xattr::get(&photo, XATTR_DONE).unwrap().is_some());
+
+    let output = run(&src, &dst, &["--clear-markers"]);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(xattr::get(&photo, XATTR_DONE).unwrap().is_none());
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{TempDir, fixture, run};
+
+#[test]
+fn move_keeps_video_sidecars_and_chapters_together() {
+    let tmp = TempDir::new("move");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("mvhd.mp4", &src.join("GX010123.MP4"));
+    fixture("mvhd.mp4", &src.join("GX020123.MP4"));
+    std::fs::write(src.join("GX010123.THM"), b"thumbnail").unwrap();
+    std::fs::create_dir(&dst).unwrap();
+
+    let output = run(&src, &dst, &["--move", "--timezone", "UTC"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(!stderr.contains("failed"), "stderr: {stderr}");
+
+    let year = dst.join("2018");
+    for name in ["2018-08-09 07.06.05-01.MP4", "2018-08-09 07.06.05-01.THM", "2018-08-09 07.06.05-02.MP4"] {
+        assert!(year.join(name).is_file(), "{name} is missing from {:?}", std::fs::read_dir(&year).unwrap().collect::<Vec<_>>());
+    }
+    assert_eq!(std::fs::read_dir(&src).unwrap().count(), 0);
+}
+
+#[test]
+fn move_leaves_takeout_json_behind() {
+    let tmp = TempDir::new("move-takeout");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("xmp-before-exif.jpg", &src.join("IMG_1234.jpg"));
+    std::fs::write(src.join("IMG_1234.jpg.json"), br#"{"photoTakenTime": {"timestamp": "0"}}"#).unwrap();
+    std::fs::create_dir(&dst).unwrap();
+
+    let output = run(&src, &dst, &["--move"]);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    let copied = std::fs::read_dir(dst.join("2019")).unwrap().map(|e| e.unwrap().file_name()).collect::<Vec<_>>();
+    assert_eq!(copied, ["2019-04-02 10.11.12.jpg"]);
+    assert!(src.join("IMG_1234.jpg.json").is_file());
+    assert!(!src.join("IMG_1234.jpg").exists());
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use common::{TempDir, fixture, run};
+
+#[test]
+fn hash_suffix_rerun_copies_nothing() {
+    let tmp = TempDir::new("rerun");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("xmp-before-exif.jpg", &src.join("a.jpg"));
+    fixture("xmp-before-exif.jpg", &src.join("b.jpg"));
+    // Same date, different contents.
+    let mut b = std::fs::read(src.join("b.jpg")).unwrap();
+    b.push(0);
+    std::fs::write(src.join("b.jpg"), b).unwrap();
+    std::fs::create_dir(&dst).unwrap();
+
+    for _ in 0..2 {
+        let output = run(&src, &dst, &["--suffix", "hash"]);
+        assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+        let copied = std::fs::read_dir(dst.join("2019")).unwrap().count();
+        assert_eq!(copied, 2);
+    }
+}
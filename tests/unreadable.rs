@@ -0,0 +1,36 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::{TempDir, fixture, run};
+
+#[test]
+fn unreadable_directory_doesnt_stop_the_run() {
+    // Root can read the directory anyway.
+    if unsafe { libc::geteuid() } == 0 {
+        eprintln!("skipping: running as root");
+        return;
+    }
+    let tmp = TempDir::new("unreadable");
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    fixture("xmp-before-exif.jpg", &src.join("a.jpg"));
+    fixture("xmp-before-exif.jpg", &src.join("locked").join("b.jpg"));
+    fixture("xmp-before-exif.jpg", &src.join("z.jpg"));
+    std::fs::create_dir(&dst).unwrap();
+    let locked = src.join("locked");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    let output = run(&src, &dst, &["--dry-run"]);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The files on either side of the unreadable directory are both still planned.
+    assert!(stdout.contains(&format!("{:?} ->", src.join("a.jpg"))), "stdout: {stdout}");
+    assert!(stdout.contains(&format!("{:?} ->", src.join("z.jpg"))), "stdout: {stdout}");
+    assert!(!stdout.contains("b.jpg"), "stdout: {stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error reading the source tree"), "stderr: {stderr}");
+}